@@ -0,0 +1,151 @@
+//! Minimal timing harness shared by the benches, so they need nothing
+//! beyond std.
+//!
+//! Every benchmark is run once to warm up, then `SAMPLES` times, and
+//! reported with its median, fastest and slowest run. Arguments after
+//! `cargo bench --bench <name> --`:
+//!
+//! - `--save <path>` writes the results as tab-separated values, one line
+//!   per benchmark, so runs on different commits can be kept side by side;
+//! - `--baseline <path>` reads such a file back and prints how much each
+//!   median changed against it;
+//! - anything else only runs benchmarks whose id contains it.
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    time::{Duration, Instant},
+};
+
+const SAMPLES: usize = 10;
+
+struct Record {
+    id: String,
+    median: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+pub struct Bench {
+    filter: Option<String>,
+    save: Option<String>,
+    baseline: HashMap<String, u128>,
+    records: Vec<Record>,
+}
+
+impl Bench {
+    pub fn from_args() -> Self {
+        let mut args = env::args().skip(1);
+        let mut bench = Bench {
+            filter: None,
+            save: None,
+            baseline: HashMap::new(),
+            records: Vec::new(),
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--save" => bench.save = args.next(),
+                "--baseline" => {
+                    let path = args.next().expect("--baseline needs a path");
+                    bench.baseline = read_report(&path);
+                }
+                // Passed by `cargo bench` itself.
+                "--bench" => {}
+                _ => bench.filter = Some(arg),
+            }
+        }
+
+        println!(
+            "{:<48} {:>12} {:>12} {:>12} {:>9}",
+            "benchmark", "median", "min", "max", "change"
+        );
+        bench
+    }
+
+    /// Times `f`, which does one full run of the benchmark per call.
+    pub fn run<F>(&mut self, id: &str, mut f: F)
+    where
+        F: FnMut(),
+    {
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| !id.contains(filter))
+        {
+            return;
+        }
+
+        f();
+        let mut samples: Vec<Duration> = (0..SAMPLES)
+            .map(|_| {
+                let start = Instant::now();
+                f();
+                start.elapsed()
+            })
+            .collect();
+        samples.sort_unstable();
+
+        let record = Record {
+            id: id.to_string(),
+            median: samples[SAMPLES / 2],
+            min: samples[0],
+            max: samples[SAMPLES - 1],
+        };
+        let change = match self.baseline.get(id) {
+            Some(&before) if before > 0 => {
+                let now = record.median.as_nanos() as f64;
+                format!("{:+.1}%", (now / before as f64 - 1.0) * 100.0)
+            }
+            _ => String::new(),
+        };
+
+        println!(
+            "{:<48} {:>12?} {:>12?} {:>12?} {:>9}",
+            record.id, record.median, record.min, record.max, change
+        );
+        self.records.push(record);
+    }
+}
+
+impl Drop for Bench {
+    fn drop(&mut self) {
+        let Some(path) = &self.save else {
+            return;
+        };
+
+        let mut report = String::from("benchmark\tmedian_ns\tmin_ns\tmax_ns\n");
+        for record in &self.records {
+            report += &format!(
+                "{}\t{}\t{}\t{}\n",
+                record.id,
+                record.median.as_nanos(),
+                record.min.as_nanos(),
+                record.max.as_nanos()
+            );
+        }
+
+        fs::write(path, report).expect("failed to write the report");
+    }
+}
+
+/// Medians by benchmark id from a file written with `--save`.
+fn read_report(path: &str) -> HashMap<String, u128> {
+    let report = fs::read_to_string(path).expect("failed to read the baseline");
+
+    report
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let id = fields.next()?;
+            let median = fields.next()?.parse().ok()?;
+            Some((id.to_string(), median))
+        })
+        .collect()
+}
+
+/// Worker threads for the parallel benches: one per core.
+pub fn threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |threads| threads.get())
+}
@@ -0,0 +1,159 @@
+//! Raw deque throughput: a single thread pushing and popping, and an owner
+//! racing 1..N thieves. Each runs on the `Mutex` backend, the lock-free
+//! Chase-Lev backend and, as a baseline, a `std::sync::mpsc` channel.
+//!
+//! Run with `cargo bench --bench deque`; see `common` for the options.
+
+mod common;
+
+use std::{
+    hint::{self, black_box},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+};
+
+use memo::work_stealing::{
+    backend::{Backend, MutexBuffer},
+    chase_lev::ChaseLev,
+    schedule::{Steal, Task, WorkStealingDeque},
+};
+
+use common::Bench;
+
+const TASKS: usize = 100_000;
+/// Tasks pushed before the owner pops them all again.
+const ROUND: usize = 1_000;
+
+/// Carries a payload so that boxing it allocates, as boxing a real task
+/// would.
+struct Item(#[allow(dead_code)] usize);
+
+impl Task for Item {
+    fn execute(self: Box<Self>) {}
+}
+
+fn push_pop<B: Backend<Item>>() {
+    let (worker, _) = WorkStealingDeque::<Item, B>::new(64);
+
+    for _ in 0..TASKS / ROUND {
+        for i in 0..ROUND {
+            worker.push(Box::new(Item(i)));
+        }
+        while let Steal::Success(item) = worker.pop() {
+            black_box(item);
+        }
+    }
+}
+
+fn push_pop_mpsc() {
+    let (sender, receiver) = mpsc::channel();
+
+    for _ in 0..TASKS / ROUND {
+        for i in 0..ROUND {
+            sender.send(Box::new(Item(i))).unwrap();
+        }
+        while let Ok(item) = receiver.try_recv() {
+            black_box(item);
+        }
+    }
+}
+
+/// The owner pushes every task, popping one back now and then, while
+/// `thieves` threads steal; then it drains whatever is left.
+fn steal_contention<B>(thieves: usize)
+where
+    B: Backend<Item> + Send + Sync,
+{
+    let (worker, stealer) = WorkStealingDeque::<Item, B>::new(64);
+    let done = AtomicBool::new(false);
+    let taken = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..thieves {
+            let stealer = stealer.clone();
+            let (done, taken) = (&done, &taken);
+
+            scope.spawn(move || loop {
+                match stealer.steal() {
+                    Steal::Success(item) => {
+                        black_box(item);
+                        taken.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Steal::Retry => hint::spin_loop(),
+                    Steal::Empty if done.load(Ordering::Acquire) => break,
+                    Steal::Empty => thread::yield_now(),
+                }
+            });
+        }
+
+        let mut popped = 0;
+        for i in 0..TASKS {
+            worker.push(Box::new(Item(i)));
+            if i % 4 == 0 && worker.pop().is_success() {
+                popped += 1;
+            }
+        }
+        while worker.pop().is_success() {
+            popped += 1;
+        }
+
+        taken.fetch_add(popped, Ordering::Relaxed);
+        done.store(true, Ordering::Release);
+    });
+
+    assert_eq!(taken.into_inner(), TASKS);
+}
+
+/// The closest a channel gets: one producer and `thieves` consumers taking
+/// turns on the receiver.
+fn steal_contention_mpsc(thieves: usize) {
+    let (sender, receiver) = mpsc::channel();
+    let receiver = Mutex::new(receiver);
+    let taken = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..thieves {
+            let (receiver, taken) = (&receiver, &taken);
+
+            scope.spawn(move || {
+                while let Ok(item) = receiver.lock().unwrap().recv() {
+                    black_box(item);
+                    taken.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+
+        for i in 0..TASKS {
+            sender.send(Box::new(Item(i))).unwrap();
+        }
+        drop(sender);
+    });
+
+    assert_eq!(taken.into_inner(), TASKS);
+}
+
+fn main() {
+    let mut bench = Bench::from_args();
+
+    bench.run("push_pop/mutex_buffer", push_pop::<MutexBuffer<Item>>);
+    bench.run("push_pop/chase_lev", push_pop::<ChaseLev<Item>>);
+    bench.run("push_pop/mpsc", push_pop_mpsc);
+
+    let most = common::threads().max(4);
+    let thieves = (0..).map(|shift| 1 << shift).take_while(|&n| n <= most);
+
+    for thieves in thieves {
+        bench.run(&format!("steal/{}/mutex_buffer", thieves), || {
+            steal_contention::<MutexBuffer<Item>>(thieves)
+        });
+        bench.run(&format!("steal/{}/chase_lev", thieves), || {
+            steal_contention::<ChaseLev<Item>>(thieves)
+        });
+        bench.run(&format!("steal/{}/mpsc", thieves), || {
+            steal_contention_mpsc(thieves)
+        });
+    }
+}
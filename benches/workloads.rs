@@ -0,0 +1,359 @@
+//! Scheduler-shaped workloads: fork-join Fibonacci, parallel quicksort and
+//! unbalanced tree search (UTS). Fibonacci and quicksort run on the
+//! `Scheduler` itself, through `install` and `join`. As a baseline, every
+//! workload also runs on a bare pool of one thread per core whose queues
+//! are either work-stealing deques, with the `Mutex` or the lock-free
+//! Chase-Lev backend, or a single shared `std::sync::mpsc` channel.
+//!
+//! Run with `cargo bench --bench workloads`; see `common` for the options.
+
+mod common;
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+};
+
+use memo::work_stealing::{
+    backend::{Backend, MutexBuffer},
+    chase_lev::ChaseLev,
+    join::join,
+    schedule::{Task, WorkStealingDeque, Worker},
+    scheduler::Scheduler,
+};
+
+use common::Bench;
+
+type Job = dyn Task + Send;
+type Spawner = Box<dyn Fn(Box<Job>)>;
+
+thread_local! {
+    /// How a running job hands new jobs to the pool it runs on.
+    static SPAWN: RefCell<Option<Spawner>> = const { RefCell::new(None) };
+}
+
+fn spawn<F>(f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    SPAWN.with(|spawn| (spawn.borrow().as_ref().expect("not on a pool thread"))(Box::new(f)));
+}
+
+/// Runs `root` and everything it spawns, returning once all of it is done.
+type Pool = fn(usize, Box<Job>);
+
+/// Pool where each thread owns a deque and steals from the others when it
+/// runs dry.
+fn deque_pool<B>(threads: usize, root: Box<Job>)
+where
+    B: Backend<Job> + Send + Sync + 'static,
+{
+    let pending = Arc::new(AtomicUsize::new(1));
+    let (workers, stealers): (Vec<_>, Vec<_>) = (0..threads)
+        .map(|_| WorkStealingDeque::<Job, B>::new(64))
+        .unzip();
+    workers[0].push(root);
+
+    thread::scope(|scope| {
+        for (index, worker) in workers.into_iter().enumerate() {
+            let stealers = &stealers;
+            let pending = pending.clone();
+
+            scope.spawn(move || {
+                let worker: Rc<Worker<Job, B>> = Rc::new(worker);
+                let (owner, local) = (worker.clone(), pending.clone());
+                SPAWN.with(|spawn| {
+                    *spawn.borrow_mut() = Some(Box::new(move |job| {
+                        local.fetch_add(1, Ordering::Relaxed);
+                        owner.push(job);
+                    }))
+                });
+
+                loop {
+                    let job = worker.pop().success().or_else(|| {
+                        (1..threads)
+                            .map(|offset| &stealers[(index + offset) % threads])
+                            .find_map(|stealer| stealer.steal_batch_and_pop(&worker).success())
+                    });
+
+                    match job {
+                        Some(job) => {
+                            job.execute();
+                            pending.fetch_sub(1, Ordering::AcqRel);
+                        }
+                        None if pending.load(Ordering::Acquire) == 0 => break,
+                        None => thread::yield_now(),
+                    }
+                }
+
+                SPAWN.with(|spawn| spawn.borrow_mut().take());
+            });
+        }
+    });
+}
+
+/// Pool where every thread takes jobs from one shared channel.
+fn channel_pool(threads: usize, root: Box<Job>) {
+    let pending = Arc::new(AtomicUsize::new(1));
+    let (sender, receiver) = mpsc::channel::<Box<Job>>();
+    let receiver = Mutex::new(receiver);
+    sender.send(root).unwrap();
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let receiver = &receiver;
+            let sender = sender.clone();
+            let pending = pending.clone();
+
+            scope.spawn(move || {
+                let local = pending.clone();
+                SPAWN.with(|spawn| {
+                    *spawn.borrow_mut() = Some(Box::new(move |job| {
+                        local.fetch_add(1, Ordering::Relaxed);
+                        sender.send(job).unwrap();
+                    }))
+                });
+
+                loop {
+                    let job = receiver.lock().unwrap().try_recv();
+
+                    match job {
+                        Ok(job) => {
+                            job.execute();
+                            pending.fetch_sub(1, Ordering::AcqRel);
+                        }
+                        Err(_) if pending.load(Ordering::Acquire) == 0 => break,
+                        Err(_) => thread::yield_now(),
+                    }
+                }
+
+                SPAWN.with(|spawn| spawn.borrow_mut().take());
+            });
+        }
+    });
+}
+
+const FIB_N: u64 = 22;
+
+/// One job per call, adding up the leaves.
+fn fib(n: u64, sum: Arc<AtomicU64>) {
+    if n < 2 {
+        sum.fetch_add(n, Ordering::Relaxed);
+        return;
+    }
+
+    let other = sum.clone();
+    spawn(move || fib(n - 1, other));
+    spawn(move || fib(n - 2, sum));
+}
+
+fn run_fib(pool: Pool, threads: usize) {
+    let sum = Arc::new(AtomicU64::new(0));
+    let root = sum.clone();
+
+    pool(threads, Box::new(move || fib(FIB_N, root)));
+    assert_eq!(sum.load(Ordering::Relaxed), 17_711);
+}
+
+/// One `join` per call.
+fn fib_join(n: u64) -> u64 {
+    if n < 2 {
+        return n;
+    }
+
+    let (a, b) = join(|| fib_join(n - 1), || fib_join(n - 2));
+    a + b
+}
+
+fn run_fib_join(scheduler: &Scheduler) {
+    assert_eq!(scheduler.install(|| fib_join(FIB_N)), 17_711);
+}
+
+const SORT_LEN: usize = 200_000;
+/// Slices shorter than this are sorted in place by the job that gets them.
+const SORT_CUTOFF: usize = 2_048;
+
+/// Part of the vector being sorted, owned by one job at a time.
+struct Slice(*mut u32, usize);
+
+unsafe impl Send for Slice {}
+
+/// Lomuto partition around the middle element. Returns where the pivot
+/// ends up.
+fn partition(slice: &mut [u32]) -> usize {
+    let len = slice.len();
+
+    slice.swap(len / 2, len - 1);
+    let pivot = slice[len - 1];
+    let mut store = 0;
+    for i in 0..len - 1 {
+        if slice[i] < pivot {
+            slice.swap(i, store);
+            store += 1;
+        }
+    }
+    slice.swap(store, len - 1);
+
+    store
+}
+
+fn quicksort(Slice(ptr, len): Slice) {
+    let slice = unsafe { std::slice::from_raw_parts_mut(ptr, len) };
+
+    if len <= SORT_CUTOFF {
+        slice.sort_unstable();
+        return;
+    }
+
+    let store = partition(slice);
+    let (left, right) = slice.split_at_mut(store);
+    let right = &mut right[1..];
+    let (left, right) = (
+        Slice(left.as_mut_ptr(), left.len()),
+        Slice(right.as_mut_ptr(), right.len()),
+    );
+    spawn(move || quicksort(left));
+    spawn(move || quicksort(right));
+}
+
+/// `join` borrows both halves, so no job outlives the slice.
+fn quicksort_join(slice: &mut [u32]) {
+    if slice.len() <= SORT_CUTOFF {
+        slice.sort_unstable();
+        return;
+    }
+
+    let store = partition(slice);
+    let (left, right) = slice.split_at_mut(store);
+    join(|| quicksort_join(left), || quicksort_join(&mut right[1..]));
+}
+
+/// The same `SORT_LEN` pseudo-random values on every run.
+fn sort_input() -> Vec<u32> {
+    let mut state = 0x2545_F491_4F6C_DD1D_u64;
+
+    (0..SORT_LEN)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u32
+        })
+        .collect()
+}
+
+fn run_quicksort(pool: Pool, threads: usize) {
+    let mut values = sort_input();
+
+    // The pool only returns once every job is done, so the vector outlives
+    // all the slices of it.
+    let root = Slice(values.as_mut_ptr(), values.len());
+    pool(threads, Box::new(move || quicksort(root)));
+
+    assert!(values.windows(2).all(|pair| pair[0] <= pair[1]));
+}
+
+fn run_quicksort_join(scheduler: &Scheduler) {
+    let mut values = sort_input();
+
+    scheduler.install(|| quicksort_join(&mut values));
+    assert!(values.windows(2).all(|pair| pair[0] <= pair[1]));
+}
+
+/// Children of the root of the binomial UTS tree.
+const UTS_ROOT_CHILDREN: u64 = 2_000;
+/// Every other node has `UTS_CHILDREN` children with probability
+/// `UTS_BRANCH_PERCENT`, and none otherwise.
+const UTS_CHILDREN: u64 = 4;
+const UTS_BRANCH_PERCENT: u64 = 24;
+
+/// SplitMix64, so the shape of the tree only depends on node ids.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+fn uts_children(node: u64) -> u64 {
+    if node == 0 {
+        UTS_ROOT_CHILDREN
+    } else if mix(node) % 100 < UTS_BRANCH_PERCENT {
+        UTS_CHILDREN
+    } else {
+        0
+    }
+}
+
+fn uts(node: u64, count: Arc<AtomicU64>) {
+    count.fetch_add(1, Ordering::Relaxed);
+
+    for child in 0..uts_children(node) {
+        let count = count.clone();
+        let child = mix(node ^ child.wrapping_mul(0x1000_0000_01B3)) | 1;
+        spawn(move || uts(child, count));
+    }
+}
+
+/// Nodes in the tree, counted without any pool.
+fn uts_size() -> u64 {
+    let mut stack = vec![0];
+    let mut count = 0;
+
+    while let Some(node) = stack.pop() {
+        count += 1;
+        for child in 0..uts_children(node) {
+            stack.push(mix(node ^ child.wrapping_mul(0x1000_0000_01B3)) | 1);
+        }
+    }
+
+    count
+}
+
+fn run_uts(pool: Pool, threads: usize, expected: u64) {
+    let count = Arc::new(AtomicU64::new(0));
+    let root = count.clone();
+
+    pool(threads, Box::new(move || uts(0, root)));
+    assert_eq!(count.load(Ordering::Relaxed), expected);
+}
+
+fn main() {
+    let mut bench = Bench::from_args();
+    let threads = common::threads();
+    let scheduler = Scheduler::new(threads);
+    let tree = uts_size();
+
+    let pools: [(&str, Pool); 3] = [
+        ("mutex_buffer", deque_pool::<MutexBuffer<Job>>),
+        ("chase_lev", deque_pool::<ChaseLev<Job>>),
+        ("mpsc", channel_pool),
+    ];
+
+    bench.run(&format!("fib/{}/scheduler", FIB_N), || {
+        run_fib_join(&scheduler)
+    });
+    for (name, pool) in pools {
+        bench.run(&format!("fib/{}/{}", FIB_N, name), || {
+            run_fib(pool, threads)
+        });
+    }
+    bench.run(&format!("quicksort/{}/scheduler", SORT_LEN), || {
+        run_quicksort_join(&scheduler)
+    });
+    for (name, pool) in pools {
+        bench.run(&format!("quicksort/{}/{}", SORT_LEN, name), || {
+            run_quicksort(pool, threads)
+        });
+    }
+    for (name, pool) in pools {
+        bench.run(&format!("uts/{}/{}", tree, name), || {
+            run_uts(pool, threads, tree)
+        });
+    }
+}
@@ -1 +1 @@
-mod work_stealing;
\ No newline at end of file
+pub mod work_stealing;
\ No newline at end of file
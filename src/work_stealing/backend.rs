@@ -0,0 +1,225 @@
+use std::{collections::VecDeque, fmt, sync::PoisonError};
+
+use super::{
+    schedule::{skip_cancelled, unless_cancelled, Steal, Task},
+    sync::{Mutex, MutexGuard},
+};
+
+/// Storage behind a `WorkStealingDeque`.
+///
+/// A backend has a single owner that pushes and pops at the bottom, and
+/// any number of thieves that steal from the top. The owner sees its tasks
+/// in LIFO order while thieves see them in FIFO order, so `pop` returns the
+/// newest task and `steal` the oldest one.
+pub trait Backend<T: ?Sized> {
+    fn with_capacity(capacity: usize) -> Self;
+
+    /// # Safety
+    ///
+    /// Must only be called by the owner of the backend.
+    unsafe fn push(&self, task: Box<T>);
+
+    /// Never returns `Retry`: if a thief wins the race for the last task,
+    /// the deque is empty.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the owner of the backend.
+    unsafe fn pop(&self) -> Steal<Box<T>>;
+
+    fn steal(&self) -> Steal<Box<T>>;
+
+    /// Steals up to half of the tasks, at most `limit`, into `dest`.
+    ///
+    /// The default takes tasks one `steal` at a time and stops at the first
+    /// one that fails, so part of a batch may already have been moved when
+    /// it returns.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the owner of `dest`, which must not be `self`.
+    unsafe fn steal_batch(&self, dest: &Self, limit: usize) -> Steal<()>
+    where
+        Self: Sized,
+    {
+        let count = self.len().div_ceil(2).min(limit);
+
+        match self.steal() {
+            Steal::Success(task) => dest.push(task),
+            Steal::Empty => return Steal::Empty,
+            Steal::Retry => return Steal::Retry,
+        }
+
+        for _ in 1..count {
+            match self.steal() {
+                Steal::Success(task) => dest.push(task),
+                _ => break,
+            }
+        }
+
+        Steal::Success(())
+    }
+
+    /// Like `steal_batch`, but returns the first stolen task instead of
+    /// pushing it into `dest`. Cancelled tasks are disposed of on the way,
+    /// so the task returned is the oldest stolen one that is not.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the owner of `dest`, which must not be `self`.
+    unsafe fn steal_batch_and_pop(&self, dest: &Self, limit: usize) -> Steal<Box<T>>
+    where
+        Self: Sized,
+        T: Task,
+    {
+        let count = self.len().div_ceil(2).min(limit).max(1);
+        let mut first = None;
+
+        for _ in 0..count {
+            let task = match skip_cancelled(self.steal()) {
+                Some(Steal::Success(task)) => task,
+                Some(Steal::Empty) if first.is_none() => return Steal::Empty,
+                Some(_) if first.is_none() => return Steal::Retry,
+                Some(_) => break,
+                None => continue,
+            };
+
+            if first.is_none() {
+                first = Some(task);
+            } else {
+                dest.push(task);
+            }
+        }
+
+        // Every task stolen was cancelled, but the victim may have more.
+        first.map_or(Steal::Retry, Steal::Success)
+    }
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+type Buffer<T> = VecDeque<Box<T>>;
+
+/// Locks `mutex` even if a thread panicked while holding it. No task runs
+/// under the queue locks and every operation on a queue leaves it
+/// consistent, so a poisoned queue is still a usable one.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Backend that guards a plain `VecDeque` with a single lock.
+pub struct MutexBuffer<T: ?Sized> {
+    buffer: Mutex<Buffer<T>>,
+}
+
+impl<T: ?Sized> MutexBuffer<T> {
+    /// Takes up to half of the tasks from the top. The lock is released
+    /// before the caller touches another buffer, so two thieves robbing
+    /// each other cannot deadlock.
+    fn take_batch(&self, limit: usize) -> Vec<Box<T>> {
+        let mut buffer = lock(&self.buffer);
+        let count = buffer.len().div_ceil(2).min(limit);
+
+        buffer.drain(..count).collect()
+    }
+}
+
+impl<T: ?Sized> Backend<T> for MutexBuffer<T> {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buffer: Mutex::new(Buffer::with_capacity(capacity)),
+        }
+    }
+
+    unsafe fn push(&self, task: Box<T>) {
+        let mut buffer = lock(&self.buffer);
+
+        buffer.push_back(task);
+    }
+
+    unsafe fn pop(&self) -> Steal<Box<T>> {
+        let mut buffer = lock(&self.buffer);
+
+        buffer.pop_back().map_or(Steal::Empty, Steal::Success)
+    }
+
+    fn steal(&self) -> Steal<Box<T>> {
+        let mut buffer = lock(&self.buffer);
+
+        buffer.pop_front().map_or(Steal::Empty, Steal::Success)
+    }
+
+    unsafe fn steal_batch(&self, dest: &Self, limit: usize) -> Steal<()> {
+        let batch = self.take_batch(limit);
+
+        if batch.is_empty() {
+            return Steal::Empty;
+        }
+        lock(&dest.buffer).extend(batch);
+
+        Steal::Success(())
+    }
+
+    unsafe fn steal_batch_and_pop(&self, dest: &Self, limit: usize) -> Steal<Box<T>>
+    where
+        T: Task,
+    {
+        let batch = self.take_batch(limit);
+        if batch.is_empty() {
+            return Steal::Empty;
+        }
+
+        // Cancelled tasks go before `dest` is locked. If there was nothing
+        // else, the victim may still have more.
+        let batch: Vec<_> = batch.into_iter().filter_map(unless_cancelled).collect();
+        let mut batch = batch.into_iter();
+        let Some(first) = batch.next() else {
+            return Steal::Retry;
+        };
+        lock(&dest.buffer).extend(batch);
+
+        Steal::Success(first)
+    }
+
+    fn len(&self) -> usize {
+        lock(&self.buffer).len()
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for MutexBuffer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MutexBuffer")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod backend_test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_mutex_buffer_survives_poisoning() {
+        let buffer = MutexBuffer::<u32>::with_capacity(4);
+        unsafe { buffer.push(Box::new(1)) };
+
+        thread::scope(|scope| {
+            let poisoner = scope.spawn(|| {
+                let _buffer = buffer.buffer.lock().unwrap();
+                panic!("poison");
+            });
+            assert!(poisoner.join().is_err());
+        });
+        assert!(buffer.buffer.is_poisoned());
+
+        unsafe { buffer.push(Box::new(2)) };
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.steal().success().map(|task| *task), Some(1));
+        assert_eq!(unsafe { buffer.pop() }.success().map(|task| *task), Some(2));
+    }
+}
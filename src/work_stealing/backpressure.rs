@@ -0,0 +1,44 @@
+use std::{error::Error, fmt};
+
+/// What `submit` and `spawn` do when the queue a task is headed for is
+/// full, see `Builder::queue_capacity`.
+///
+/// From a worker that queue is the worker's own deque, from any other
+/// thread it is the injector of the task's priority. Tasks the scheduler
+/// queues for itself, such as the second half of a `join` or a woken
+/// future, always spill into the injector instead, since dropping or
+/// delaying them could deadlock whoever waits for them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backpressure {
+    /// Drops the task. `spawn` reports it through the join handle, whose
+    /// `join` fails with a `Rejected` payload.
+    Reject,
+    /// Waits until there is room. Any thread but a worker parks until a
+    /// worker takes tasks out of the injector, or the scheduler shuts
+    /// down.
+    ///
+    /// A worker cannot wait for its own deque to drain, so it makes room by
+    /// running queued tasks itself. Tasks run that way may hit the limit
+    /// again, which nests another round on the stack; past a few levels,
+    /// the task spills into the injector instead.
+    Block,
+    /// Queues the task in the injector regardless of its size, so only the
+    /// workers' own deques stay bounded.
+    #[default]
+    SpillToInjector,
+    /// Runs the task right away on the submitting thread. Panics go to the
+    /// panic handler, as they would on a worker.
+    RunInline,
+}
+
+/// Error for a task dropped under `Backpressure::Reject`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rejected;
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("task rejected, the queue is full")
+    }
+}
+
+impl Error for Rejected {}
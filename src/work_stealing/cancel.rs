@@ -0,0 +1,137 @@
+use std::{
+    error::Error,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+
+#[derive(Default)]
+struct Node {
+    cancelled: AtomicBool,
+    /// Tokens from `child_token`, dropped ones are pruned as new ones come.
+    children: Mutex<Vec<Weak<Node>>>,
+}
+
+/// Flag that withdraws the tasks spawned with it, see
+/// `Handle::spawn_with_token`.
+///
+/// Clones share the same flag. `child_token` makes a token that is
+/// cancelled along with this one but can also be cancelled on its own, so
+/// a group of tasks can be aborted as a whole or in parts. Tasks that
+/// already run are not interrupted; a long-running one can keep a clone
+/// and check `is_cancelled` every now and then.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    node: Arc<Node>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a token that is cancelled whenever this one is. Cancelling
+    /// the child does not touch this one.
+    pub fn child_token(&self) -> Self {
+        let child = Arc::new(Node::default());
+        let mut children = self.node.children.lock().unwrap();
+
+        // Checked under the lock, so a concurrent `cancel` either sees the
+        // child or the child sees the flag.
+        if self.is_cancelled() {
+            child.cancelled.store(true, Ordering::Release);
+        } else {
+            if children.len() == children.capacity() {
+                children.retain(|child| child.strong_count() > 0);
+            }
+            children.push(Arc::downgrade(&child));
+        }
+
+        Self { node: child }
+    }
+
+    /// Cancels this token and all tokens derived from it.
+    pub fn cancel(&self) {
+        let mut pending = vec![self.node.clone()];
+
+        while let Some(node) = pending.pop() {
+            let mut children = node.children.lock().unwrap();
+            if node.cancelled.swap(true, Ordering::AcqRel) {
+                continue;
+            }
+
+            pending.extend(children.drain(..).filter_map(|child| child.upgrade()));
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.node.cancelled.load(Ordering::Acquire)
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Error for a task whose token was cancelled before it ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("task cancelled before it ran")
+    }
+}
+
+impl Error for Cancelled {}
+
+#[cfg(test)]
+mod cancel_test {
+    use super::*;
+
+    #[test]
+    fn test_clones_share_the_flag() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+
+        assert!(!token.is_cancelled());
+        clone.cancel();
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn test_cancel_reaches_descendants() {
+        let root = CancellationToken::new();
+        let child = root.child_token();
+        let grandchild = child.child_token();
+        let sibling = root.child_token();
+
+        child.cancel();
+        assert!(child.is_cancelled() && grandchild.is_cancelled());
+        assert!(!root.is_cancelled() && !sibling.is_cancelled());
+
+        root.cancel();
+        assert!(sibling.is_cancelled());
+        assert!(root.child_token().is_cancelled());
+    }
+
+    #[test]
+    fn test_dropped_children_are_pruned() {
+        let root = CancellationToken::new();
+
+        for _ in 0..1000 {
+            drop(root.child_token());
+        }
+        let kept = root.child_token();
+
+        assert!(root.node.children.lock().unwrap().len() < 100);
+        root.cancel();
+        assert!(kept.is_cancelled());
+    }
+}
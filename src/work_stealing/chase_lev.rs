@@ -0,0 +1,332 @@
+use std::{cell::UnsafeCell, fmt, mem::MaybeUninit, ptr};
+
+use super::{
+    backend::Backend,
+    schedule::Steal,
+    sync::{fence, AtomicIsize, AtomicPtr, AtomicUsize, Ordering},
+};
+
+const MIN_CAPACITY: usize = 16;
+
+type Slot<T> = UnsafeCell<MaybeUninit<Box<T>>>;
+
+/// Circular array of task slots. The capacity is always a power of two
+/// so that indices can wrap with a mask.
+struct Array<T: ?Sized> {
+    slots: Box<[Slot<T>]>,
+}
+
+impl<T: ?Sized> Array<T> {
+    fn new(capacity: usize) -> Self {
+        let slots = (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect();
+
+        Self { slots }
+    }
+
+    fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn slot(&self, index: isize) -> *mut MaybeUninit<Box<T>> {
+        self.slots[index as usize & (self.capacity() - 1)].get()
+    }
+
+    unsafe fn write(&self, index: isize, task: Box<T>) {
+        ptr::write_volatile(self.slot(index), MaybeUninit::new(task));
+    }
+
+    /// Reads a bitwise copy of the slot. The copy only becomes an owned
+    /// task once the caller has claimed the index.
+    unsafe fn read(&self, index: isize) -> MaybeUninit<Box<T>> {
+        ptr::read_volatile(self.slot(index))
+    }
+}
+
+/// Lock-free Chase-Lev deque over a growable circular array.
+///
+/// The owner pushes and pops at `bottom`, thieves take from `top`.
+///
+/// The array never shrinks. Arrays it outgrew are kept while a thief may
+/// still be reading from one, and freed by the owner's next `push` or
+/// `pop` that finds no steal in progress. As each one is half the size of
+/// the next, they never take up more than the current array does.
+pub struct ChaseLev<T: ?Sized> {
+    top: AtomicIsize,
+    bottom: AtomicIsize,
+    array: AtomicPtr<Array<T>>,
+    /// Arrays replaced by `grow`, only touched by the owner.
+    retired: UnsafeCell<Vec<Array<T>>>,
+    /// Thieves between announcing a steal and reading their slot.
+    thieves: AtomicUsize,
+}
+
+unsafe impl<T: ?Sized + Send> Send for ChaseLev<T> {}
+unsafe impl<T: ?Sized + Send> Sync for ChaseLev<T> {}
+
+impl<T: ?Sized> ChaseLev<T> {
+    pub fn capacity(&self) -> usize {
+        unsafe { (*self.array.load(Ordering::Relaxed)).capacity() }
+    }
+
+    /// Doubles the array, copying the live range `top..bottom` over.
+    unsafe fn grow(&self, top: isize, bottom: isize) -> *mut Array<T> {
+        let old = self.array.load(Ordering::Relaxed);
+        let new = Array::new((*old).capacity() * 2);
+
+        for index in top..bottom {
+            ptr::copy_nonoverlapping((*old).slot(index), new.slot(index), 1);
+        }
+
+        let new = Box::into_raw(Box::new(new));
+        self.array.store(new, Ordering::Release);
+        (*self.retired.get()).push(*Box::from_raw(old));
+
+        new
+    }
+
+    /// Frees the retired arrays unless a thief is in the middle of a steal.
+    ///
+    /// A thief announces itself before the `SeqCst` fence that precedes
+    /// its load of `array`, and this fences after `grow` replaced it, so
+    /// either this sees the thief or the thief sees the new array. Thieves
+    /// leave with a release once they have read their slot.
+    unsafe fn reclaim(&self) {
+        let retired = &mut *self.retired.get();
+        if retired.is_empty() {
+            return;
+        }
+
+        fence(Ordering::SeqCst);
+        if self.thieves.load(Ordering::Acquire) == 0 {
+            retired.clear();
+        }
+    }
+}
+
+impl<T: ?Sized> Backend<T> for ChaseLev<T> {
+    fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY).next_power_of_two();
+
+        Self {
+            top: AtomicIsize::new(0),
+            bottom: AtomicIsize::new(0),
+            array: AtomicPtr::new(Box::into_raw(Box::new(Array::new(capacity)))),
+            retired: UnsafeCell::new(Vec::new()),
+            thieves: AtomicUsize::new(0),
+        }
+    }
+
+    unsafe fn push(&self, task: Box<T>) {
+        self.reclaim();
+
+        let bottom = self.bottom.load(Ordering::Relaxed);
+        let top = self.top.load(Ordering::Acquire);
+        let mut array = self.array.load(Ordering::Relaxed);
+
+        if bottom - top >= (*array).capacity() as isize {
+            array = self.grow(top, bottom);
+        }
+
+        (*array).write(bottom, task);
+        fence(Ordering::Release);
+        self.bottom.store(bottom + 1, Ordering::Relaxed);
+    }
+
+    unsafe fn pop(&self) -> Steal<Box<T>> {
+        self.reclaim();
+
+        let bottom = self.bottom.load(Ordering::Relaxed) - 1;
+        let array = self.array.load(Ordering::Relaxed);
+        self.bottom.store(bottom, Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+        let top = self.top.load(Ordering::Relaxed);
+
+        if top > bottom {
+            self.bottom.store(bottom + 1, Ordering::Relaxed);
+            return Steal::Empty;
+        }
+
+        let task = (*array).read(bottom);
+
+        if top == bottom {
+            // Last element: race the thieves for it.
+            let won = self
+                .top
+                .compare_exchange(top, top + 1, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok();
+            self.bottom.store(bottom + 1, Ordering::Relaxed);
+
+            if !won {
+                return Steal::Empty;
+            }
+        }
+
+        Steal::Success(task.assume_init())
+    }
+
+    fn steal(&self) -> Steal<Box<T>> {
+        // Keeps the owner from freeing the array read below, see `reclaim`.
+        self.thieves.fetch_add(1, Ordering::Relaxed);
+        let top = self.top.load(Ordering::Acquire);
+        fence(Ordering::SeqCst);
+        let bottom = self.bottom.load(Ordering::Acquire);
+
+        if top >= bottom {
+            self.thieves.fetch_sub(1, Ordering::Release);
+            return Steal::Empty;
+        }
+
+        let array = self.array.load(Ordering::Acquire);
+        let task = unsafe { (*array).read(top) };
+        self.thieves.fetch_sub(1, Ordering::Release);
+
+        if self
+            .top
+            .compare_exchange(top, top + 1, Ordering::SeqCst, Ordering::Relaxed)
+            .is_err()
+        {
+            return Steal::Retry;
+        }
+
+        Steal::Success(unsafe { task.assume_init() })
+    }
+
+    fn len(&self) -> usize {
+        let bottom = self.bottom.load(Ordering::Relaxed);
+        let top = self.top.load(Ordering::Relaxed);
+
+        (bottom - top).max(0) as usize
+    }
+}
+
+impl<T: ?Sized> Drop for ChaseLev<T> {
+    fn drop(&mut self) {
+        // Plain loads rather than `get_mut`, which loom's atomics lack.
+        let top = self.top.load(Ordering::Relaxed);
+        let bottom = self.bottom.load(Ordering::Relaxed);
+        let array = unsafe { Box::from_raw(self.array.load(Ordering::Relaxed)) };
+
+        for index in top..bottom {
+            unsafe { (*array.slot(index)).assume_init_drop() };
+        }
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for ChaseLev<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChaseLev")
+            .field("top", &self.top.load(Ordering::Relaxed))
+            .field("bottom", &self.bottom.load(Ordering::Relaxed))
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod chase_lev_test {
+    use super::*;
+    use std::{
+        collections::HashSet,
+        sync::{atomic::AtomicBool, Arc},
+        thread,
+    };
+
+    #[test]
+    fn test_grow_keeps_order() {
+        let deque: ChaseLev<u32> = ChaseLev::with_capacity(2);
+
+        for i in 0..100 {
+            unsafe { deque.push(Box::new(i)) };
+        }
+        assert!(deque.capacity() >= 100);
+        assert_eq!(deque.len(), 100);
+
+        assert_eq!(deque.steal().success().map(|task| *task), Some(0));
+        assert_eq!(unsafe { deque.pop() }.success().map(|task| *task), Some(99));
+        assert_eq!(deque.len(), 98);
+    }
+
+    #[test]
+    fn test_retired_arrays_are_freed() {
+        let deque: ChaseLev<u32> = ChaseLev::with_capacity(MIN_CAPACITY);
+
+        // The last push outgrows the array.
+        for i in 0..=MIN_CAPACITY as u32 {
+            unsafe { deque.push(Box::new(i)) };
+        }
+        assert_eq!(unsafe { (*deque.retired.get()).len() }, 1);
+
+        // Without any thief around, the next owner operation frees it.
+        assert_eq!(
+            unsafe { deque.pop() }.success().map(|task| *task),
+            Some(MIN_CAPACITY as u32)
+        );
+        assert!(unsafe { (*deque.retired.get()).is_empty() });
+    }
+
+    #[test]
+    fn test_drop_releases_remaining_tasks() {
+        let counter = Arc::new(());
+        let deque: ChaseLev<Arc<()>> = ChaseLev::with_capacity(2);
+
+        for _ in 0..40 {
+            unsafe { deque.push(Box::new(counter.clone())) };
+        }
+        drop(deque);
+
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn test_concurrent_steal() {
+        const TASKS: usize = 10_000;
+        const THIEVES: usize = 3;
+
+        let deque: Arc<ChaseLev<usize>> = Arc::new(ChaseLev::with_capacity(4));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let thieves: Vec<_> = (0..THIEVES)
+            .map(|_| {
+                let deque = deque.clone();
+                let done = done.clone();
+
+                thread::spawn(move || {
+                    let mut stolen = Vec::new();
+                    while !done.load(Ordering::Acquire) || !deque.is_empty() {
+                        match deque.steal() {
+                            Steal::Success(task) => stolen.push(*task),
+                            Steal::Retry => {}
+                            Steal::Empty => thread::yield_now(),
+                        }
+                    }
+                    stolen
+                })
+            })
+            .collect();
+
+        let mut seen = Vec::new();
+        for i in 0..TASKS {
+            unsafe { deque.push(Box::new(i)) };
+
+            if i % 3 == 0 {
+                if let Steal::Success(task) = unsafe { deque.pop() } {
+                    seen.push(*task);
+                }
+            }
+        }
+        while let Steal::Success(task) = unsafe { deque.pop() } {
+            seen.push(*task);
+        }
+        done.store(true, Ordering::Release);
+
+        for thief in thieves {
+            seen.extend(thief.join().unwrap());
+        }
+
+        let unique: HashSet<_> = seen.iter().copied().collect();
+        assert_eq!(seen.len(), TASKS);
+        assert_eq!(unique.len(), TASKS);
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    sync::Mutex,
+    task::Waker,
+    time::{Duration, Instant},
+};
+
+/// Source of time for a scheduler's timers.
+pub trait Clock: Send + Sync {
+    /// Time elapsed since the clock's own starting point. Must never go
+    /// backwards.
+    fn now(&self) -> Duration;
+
+    /// Registers `waker` to be woken whenever the clock jumps ahead. Every
+    /// scheduler built on the clock registers one, since its idle workers
+    /// only wake up when the next timer is due in real time. Clocks that
+    /// follow real time have nothing to do here.
+    fn subscribe(&self, waker: Waker) {
+        let _ = waker;
+    }
+}
+
+/// Real time, measured from when the clock was created.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Clock that only moves when told to, so timer tests neither sleep nor
+/// depend on how fast the machine is.
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: Mutex<Duration>,
+    subscribers: Mutex<Vec<Waker>>,
+}
+
+impl MockClock {
+    /// Creates a clock standing at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the clock ahead and wakes the schedulers running on it, so
+    /// their timers fire right away.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+
+        for waker in self.subscribers.lock().unwrap().iter() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    fn subscribe(&self, waker: Waker) {
+        self.subscribers.lock().unwrap().push(waker);
+    }
+}
+
+#[cfg(test)]
+mod clock_test {
+    use super::*;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        task::Wake,
+    };
+
+    struct CountWakes(AtomicUsize);
+
+    impl Wake for CountWakes {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_mock_clock_moves_only_on_advance() {
+        let clock = MockClock::new();
+        assert_eq!(clock.now(), Duration::ZERO);
+
+        clock.advance(Duration::from_millis(1500));
+        clock.advance(Duration::from_millis(500));
+
+        assert_eq!(clock.now(), Duration::from_secs(2));
+        assert_eq!(clock.now(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_advance_wakes_subscribers() {
+        let clock = MockClock::new();
+        let wakes = Arc::new(CountWakes(AtomicUsize::new(0)));
+
+        clock.subscribe(Waker::from(wakes.clone()));
+        clock.advance(Duration::from_millis(1));
+        clock.advance(Duration::from_millis(1));
+
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_system_clock_is_monotonic() {
+        let clock = SystemClock::new();
+        let earlier = clock.now();
+
+        assert!(clock.now() >= earlier);
+    }
+}
@@ -0,0 +1,531 @@
+use std::{
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::{pin, Pin},
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+use super::{
+    join_handle::{JoinHandle, Packet, PacketGuard},
+    priority::Priority,
+    schedule::Task,
+    scheduler::{self, Handle, WeakHandle},
+};
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Not queued; waiting for its waker.
+const IDLE: u8 = 0;
+/// Sitting in a deque or the injector.
+const SCHEDULED: u8 = 1;
+const RUNNING: u8 = 2;
+/// Woken while running, so it goes back into a queue after the poll.
+const NOTIFIED: u8 = 3;
+const COMPLETE: u8 = 4;
+
+/// A spawned future together with the state that makes sure it is queued
+/// at most once and polled by one worker at a time.
+struct AsyncTask {
+    state: AtomicU8,
+    future: Mutex<Option<BoxFuture>>,
+    scheduler: WeakHandle,
+}
+
+impl AsyncTask {
+    fn run(self: Arc<Self>) {
+        self.state.store(RUNNING, Ordering::Release);
+
+        let waker = Waker::from(self.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = self.future.lock().unwrap();
+
+        let Some(poll) = future.as_mut().map(|future| future.as_mut().poll(&mut cx)) else {
+            return;
+        };
+
+        if poll.is_ready() {
+            *future = None;
+            self.state.store(COMPLETE, Ordering::Release);
+            return;
+        }
+        drop(future);
+
+        if self
+            .state
+            .compare_exchange(RUNNING, IDLE, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            // Woken during the poll. Going to the back of the injector
+            // keeps a future that keeps waking itself from hogging a worker.
+            self.state.store(SCHEDULED, Ordering::Release);
+            match self.scheduler.upgrade() {
+                Some(handle) => handle.inject_job(Box::new(Runnable(self))),
+                None => self.abandon(),
+            }
+        }
+    }
+
+    /// Drops the future once nothing is left to poll it, which completes
+    /// the join handle as abandoned. Wakers may keep the task itself alive
+    /// for much longer than that.
+    fn abandon(&self) {
+        self.state.store(COMPLETE, Ordering::Release);
+
+        // Not under the lock, dropping the future may wake other tasks.
+        let future = self.future.lock().unwrap().take();
+        drop(future);
+    }
+}
+
+impl Wake for AsyncTask {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let mut state = self.state.load(Ordering::Acquire);
+
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+
+            match self
+                .state
+                .compare_exchange(state, next, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(actual) => state = actual,
+            }
+        }
+
+        if state == IDLE {
+            match self.scheduler.upgrade() {
+                Some(handle) => handle.push_woken_job(Box::new(Runnable(self.clone()))),
+                None => self.abandon(),
+            }
+        }
+    }
+}
+
+/// What actually sits in a deque for an `AsyncTask`.
+struct Runnable(Arc<AsyncTask>);
+
+impl Task for Runnable {
+    fn execute(self: Box<Self>) {
+        self.0.run();
+    }
+}
+
+/// Polls the inner future inside `catch_unwind`.
+struct CatchUnwind<F>(F);
+
+impl<F> Future for CatchUnwind<F>
+where
+    F: Future,
+{
+    type Output = thread::Result<F::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Structural pinning: the inner future is never moved out.
+        let future = unsafe { self.map_unchecked_mut(|this| &mut this.0) };
+
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+/// Wakes a thread blocked in `block_on`. On a worker, that takes waking
+/// the parked workers of its pool too.
+struct ThreadWaker {
+    thread: Thread,
+    notified: AtomicBool,
+    worker: Mutex<Option<Waker>>,
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.notified.store(true, Ordering::Release);
+        self.thread.unpark();
+
+        if let Some(worker) = &*self.worker.lock().unwrap() {
+            worker.wake_by_ref();
+        }
+    }
+}
+
+impl Handle {
+    /// Runs a future on the pool and returns a handle to its output.
+    ///
+    /// The future is polled by whichever worker picks it up. When its waker
+    /// fires on a worker, the task goes into that worker's LIFO slot and is
+    /// polled next, unless the slot already had its way a few times in a
+    /// row. Idle peers take it from there if the worker stays busy. A wake
+    /// from any other thread goes through the injector.
+    pub fn spawn_future<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let packet = Packet::new();
+        let handle = JoinHandle::new(packet.clone());
+
+        // Whenever the task is dropped before the future finishes, the
+        // guard goes with it and the handle stops waiting.
+        let packet = PacketGuard::new(packet);
+        let future = async move {
+            packet.complete(CatchUnwind(future).await);
+        };
+        let task = Arc::new(AsyncTask {
+            state: AtomicU8::new(SCHEDULED),
+            future: Mutex::new(Some(Box::pin(future))),
+            scheduler: self.downgrade(),
+        });
+
+        self.push_job(Box::new(Runnable(task)), Priority::Normal);
+
+        handle
+    }
+
+    /// Drives `future` to completion on the current thread.
+    ///
+    /// Between polls the thread parks until the future is woken, or, on a
+    /// worker thread, keeps running other tasks.
+    pub fn block_on<F>(&self, future: F) -> F::Output
+    where
+        F: Future,
+    {
+        let mut future = pin!(future);
+        let waker = Arc::new(ThreadWaker {
+            thread: thread::current(),
+            notified: AtomicBool::new(false),
+            worker: Mutex::new(None),
+        });
+        let notified = || waker.notified.load(Ordering::Acquire);
+        let cx_waker = Waker::from(waker.clone());
+        let mut cx = Context::from_waker(&cx_waker);
+
+        loop {
+            waker.notified.store(false, Ordering::Release);
+
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+
+            let register = |worker| *waker.worker.lock().unwrap() = Some(worker);
+            if !scheduler::help_until(notified, register) {
+                while !notified() {
+                    thread::park();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod executor_test {
+    use super::*;
+    use crate::work_stealing::{join_handle::Abandoned, scheduler::Scheduler};
+    use std::{
+        collections::VecDeque,
+        sync::{atomic::AtomicUsize, mpsc},
+        time::Duration,
+    };
+
+    /// Returns `Pending` `count` times, waking itself each time.
+    struct YieldNow {
+        count: usize,
+    }
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.count == 0 {
+                return Poll::Ready(());
+            }
+
+            self.count -= 1;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    /// Wakes its partner on every poll, then waits to be woken back, until
+    /// `stop` is set.
+    struct PingPong {
+        stop: Arc<AtomicBool>,
+        polls: Arc<AtomicUsize>,
+        mine: Arc<Mutex<Option<Waker>>>,
+        theirs: Arc<Mutex<Option<Waker>>>,
+    }
+
+    impl Future for PingPong {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            self.polls.fetch_add(1, Ordering::Relaxed);
+            *self.mine.lock().unwrap() = Some(cx.waker().clone());
+
+            let partner = self.theirs.lock().unwrap().take();
+            if let Some(partner) = partner {
+                partner.wake();
+            }
+
+            if self.stop.load(Ordering::Relaxed) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    struct OneshotState<T> {
+        value: Option<T>,
+        waker: Option<Waker>,
+    }
+
+    /// Single-value channel whose receiver is a future.
+    struct Receiver<T>(Arc<Mutex<OneshotState<T>>>);
+
+    struct Sender<T>(Arc<Mutex<OneshotState<T>>>);
+
+    fn oneshot<T>() -> (Sender<T>, Receiver<T>) {
+        let state = Arc::new(Mutex::new(OneshotState {
+            value: None,
+            waker: None,
+        }));
+
+        (Sender(state.clone()), Receiver(state))
+    }
+
+    impl<T> Sender<T> {
+        fn send(self, value: T) {
+            let waker = {
+                let mut state = self.0.lock().unwrap();
+                state.value = Some(value);
+                state.waker.take()
+            };
+
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+
+    impl<T> Future for Receiver<T> {
+        type Output = T;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+            let mut state = self.0.lock().unwrap();
+
+            match state.value.take() {
+                Some(value) => Poll::Ready(value),
+                None => {
+                    state.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_block_on_ready_future() {
+        let scheduler = Scheduler::new(1);
+
+        assert_eq!(scheduler.block_on(async { 1 + 2 }), 3);
+    }
+
+    #[test]
+    fn test_spawn_future_returns_output() {
+        let scheduler = Scheduler::new(2);
+
+        let handle = scheduler.spawn_future(async {
+            YieldNow { count: 10 }.await;
+            "done"
+        });
+
+        assert_eq!(scheduler.block_on(handle).unwrap(), "done");
+    }
+
+    #[test]
+    fn test_wake_from_another_thread() {
+        let scheduler = Scheduler::new(2);
+        let (sender, receiver) = oneshot();
+
+        let handle = scheduler.spawn_future(async move { receiver.await * 2 });
+        let sender = thread::spawn(move || sender.send(21));
+
+        assert_eq!(handle.join().unwrap(), 42);
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn test_futures_await_each_other() {
+        let scheduler = Scheduler::new(3);
+        let handle = scheduler.handle();
+
+        let total = scheduler.block_on(async move {
+            let mut handles = VecDeque::new();
+            for i in 0..50u64 {
+                handles.push_back(handle.spawn_future(async move {
+                    YieldNow { count: 3 }.await;
+                    i
+                }));
+            }
+
+            let mut total = 0;
+            while let Some(handle) = handles.pop_front() {
+                total += handle.await.unwrap();
+            }
+            total
+        });
+
+        assert_eq!(total, (0..50).sum());
+    }
+
+    #[test]
+    fn test_panicking_future() {
+        let scheduler = Scheduler::new(1);
+
+        let handle = scheduler.spawn_future(async {
+            YieldNow { count: 1 }.await;
+            panic!("future failed");
+        });
+        let payload = handle.join().unwrap_err();
+
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"future failed"));
+        assert_eq!(scheduler.block_on(scheduler.spawn_future(async { 5 })).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_woken_task_runs_next() {
+        let scheduler = Scheduler::new(1);
+        let handle = scheduler.handle();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let polls = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = oneshot::<()>();
+
+        let task = {
+            let order = order.clone();
+            let polls = polls.clone();
+            handle.spawn_future(async move {
+                polls.fetch_add(1, Ordering::Relaxed);
+                receiver.await;
+                order.lock().unwrap().push("woken");
+            })
+        };
+
+        let order_for_job = order.clone();
+        let queued = scheduler
+            .spawn(move || {
+                // The future is parked on the receiver by now, since the
+                // only worker runs it before this job.
+                assert_eq!(polls.load(Ordering::Relaxed), 1);
+
+                sender.send(());
+                let order = order_for_job.clone();
+                Handle::current().unwrap().spawn(move || {
+                    order.lock().unwrap().push("queued");
+                })
+            })
+            .join()
+            .unwrap();
+
+        task.join().unwrap();
+        queued.join().unwrap();
+        assert_eq!(*order.lock().unwrap(), vec!["woken", "queued"]);
+    }
+
+    #[test]
+    fn test_waking_futures_do_not_starve_queue() {
+        let scheduler = Scheduler::new(1);
+        let stop = Arc::new(AtomicBool::new(false));
+        let polls = Arc::new(AtomicUsize::new(0));
+        let wakers: [Arc<Mutex<Option<Waker>>>; 2] = Default::default();
+
+        let tasks: Vec<_> = (0..2)
+            .map(|i| {
+                scheduler.spawn_future(PingPong {
+                    stop: stop.clone(),
+                    polls: polls.clone(),
+                    mine: wakers[i].clone(),
+                    theirs: wakers[1 - i].clone(),
+                })
+            })
+            .collect();
+        while polls.load(Ordering::Relaxed) < 100 {
+            thread::yield_now();
+        }
+
+        // Each future lands in the LIFO slot of the only worker whenever
+        // the other one wakes it, so only the budget lets this one in.
+        let (sender, receiver) = mpsc::channel();
+        scheduler.submit(move || sender.send(()).unwrap());
+        let ran = receiver.recv_timeout(Duration::from_secs(5));
+
+        stop.store(true, Ordering::Relaxed);
+        assert_eq!(ran, Ok(()));
+        for task in tasks {
+            task.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_future_woken_after_drop_is_abandoned() {
+        let scheduler = Scheduler::new(1);
+        let (sender, receiver) = oneshot::<()>();
+        let task = scheduler.spawn_future(receiver);
+
+        while sender.0.lock().unwrap().waker.is_none() {
+            thread::yield_now();
+        }
+        drop(scheduler);
+        sender.send(());
+
+        let payload = task.join().unwrap_err();
+        assert_eq!(payload.downcast_ref::<Abandoned>(), Some(&Abandoned));
+    }
+
+    #[test]
+    fn test_peer_takes_woken_task_of_busy_worker() {
+        let scheduler = Scheduler::new(2);
+        let (wake, woken) = oneshot::<()>();
+        let (sender, receiver) = mpsc::channel();
+
+        let task = scheduler.spawn_future(async move {
+            woken.await;
+            sender.send(()).unwrap();
+        });
+        while wake.0.lock().unwrap().waker.is_none() {
+            thread::yield_now();
+        }
+
+        // The wake puts the future into the LIFO slot of a worker that then
+        // blocks until the future has run.
+        let ran = scheduler
+            .spawn(move || {
+                wake.send(());
+                receiver.recv_timeout(Duration::from_secs(5))
+            })
+            .join()
+            .unwrap();
+
+        assert_eq!(ran, Ok(()));
+        task.join().unwrap();
+    }
+}
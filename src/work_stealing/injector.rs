@@ -0,0 +1,320 @@
+use std::{collections::VecDeque, fmt};
+
+use super::{
+    backend::{lock, Backend},
+    schedule::{skip_cancelled, Steal, Task, Worker, MAX_BATCH},
+    sync::Mutex,
+};
+
+/// Multi-producer FIFO queue shared by every worker of a pool.
+///
+/// Threads outside the pool push here; workers take tasks out in batches
+/// when their own deque is empty and stealing from peers failed.
+pub struct Injector<T>
+where
+    T: Task + ?Sized,
+{
+    queue: Mutex<VecDeque<Box<T>>>,
+}
+
+impl<T> Injector<T>
+where
+    T: Task + ?Sized,
+{
+    pub fn new() -> Self {
+        Self {
+            queue: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn push(&self, task: Box<T>) {
+        lock(&self.queue).push_back(task);
+    }
+
+    /// Takes the oldest task that is not cancelled.
+    pub fn steal(&self) -> Steal<Box<T>> {
+        loop {
+            // Cancelling a task may queue another, so not under the lock.
+            let task = lock(&self.queue).pop_front();
+
+            if let Some(steal) = skip_cancelled(task.map_or(Steal::Empty, Steal::Success)) {
+                return steal;
+            }
+        }
+    }
+
+    /// Moves up to half of the queued tasks, at most `MAX_BATCH` and no
+    /// more than a bounded `dest` has room for, into `dest`. Returns how
+    /// many were moved.
+    pub fn steal_batch<B>(&self, dest: &Worker<T, B>) -> usize
+    where
+        B: Backend<T>,
+    {
+        let mut queue = lock(&self.queue);
+        let count = queue.len().div_ceil(2).min(MAX_BATCH).min(dest.room());
+
+        for task in queue.drain(..count) {
+            dest.push(task);
+        }
+
+        count
+    }
+
+    /// Like `steal_batch`, but hands the oldest task of the batch that is
+    /// not cancelled straight back to the caller instead of pushing it.
+    pub fn steal_batch_and_pop<B>(&self, dest: &Worker<T, B>) -> Steal<Box<T>>
+    where
+        B: Backend<T>,
+    {
+        loop {
+            let mut cancelled = Vec::new();
+            let first = {
+                let mut queue = lock(&self.queue);
+                let room = dest.room().saturating_add(1);
+                let count = queue.len().div_ceil(2).min(MAX_BATCH).min(room);
+                let mut first = None;
+
+                for task in queue.drain(..count) {
+                    if task.is_cancelled() {
+                        cancelled.push(task);
+                    } else if first.is_none() {
+                        first = Some(task);
+                    } else {
+                        dest.push(task);
+                    }
+                }
+                first
+            };
+
+            // Cancelling a task may queue another, so not under the lock.
+            let empty = cancelled.is_empty();
+            cancelled.into_iter().for_each(|task| task.cancel());
+
+            match first {
+                Some(task) => return Steal::Success(task),
+                None if empty => return Steal::Empty,
+                // The whole batch was cancelled, the next one may not be.
+                None => {}
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        lock(&self.queue).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> fmt::Debug for Injector<T>
+where
+    T: Task + ?Sized,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Injector")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl<T> Default for Injector<T>
+where
+    T: Task + ?Sized,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod injector_test {
+    use super::*;
+    use crate::work_stealing::{
+        cancel::CancellationToken,
+        join_handle::{JoinHandle, Packet, Spawned},
+        schedule::WorkStealingDeque,
+    };
+    use std::{sync::Arc, thread};
+
+    struct TestTask(pub u32);
+
+    impl Task for TestTask {
+        fn execute(self: Box<Self>) {}
+    }
+
+    #[test]
+    fn test_steal_in_push_order() {
+        let injector = Injector::new();
+
+        for i in 0..3 {
+            injector.push(Box::new(TestTask(i)));
+        }
+
+        assert_eq!(injector.steal().success().map(|task| task.0), Some(0));
+        assert_eq!(injector.steal().success().map(|task| task.0), Some(1));
+        assert_eq!(injector.steal().success().map(|task| task.0), Some(2));
+        assert!(injector.steal().is_empty());
+    }
+
+    #[test]
+    fn test_steal_batch_moves_half() {
+        let injector = Injector::new();
+        let (worker, _) = WorkStealingDeque::<TestTask>::new(8);
+
+        for i in 0..10 {
+            injector.push(Box::new(TestTask(i)));
+        }
+
+        assert_eq!(injector.steal_batch(&worker), 5);
+        assert_eq!(worker.len(), 5);
+        assert_eq!(injector.len(), 5);
+
+        let first = injector.steal_batch_and_pop(&worker);
+        assert_eq!(first.success().map(|task| task.0), Some(5));
+        assert_eq!(worker.len(), 7);
+        assert_eq!(injector.len(), 2);
+    }
+
+    #[test]
+    fn test_steal_batch_is_capped() {
+        let injector = Injector::new();
+        let (worker, _) = WorkStealingDeque::<TestTask>::new(8);
+
+        for i in 0..1000 {
+            injector.push(Box::new(TestTask(i)));
+        }
+
+        assert_eq!(injector.steal_batch(&worker), MAX_BATCH);
+        assert!(injector.steal_batch_and_pop(&worker).is_success());
+        assert_eq!(worker.len(), 2 * MAX_BATCH - 1);
+    }
+
+    #[test]
+    fn test_steal_batch_into_bounded_deque() {
+        let injector = Injector::new();
+        let (worker, _) = WorkStealingDeque::<TestTask>::bounded(2);
+
+        for i in 0..10 {
+            injector.push(Box::new(TestTask(i)));
+        }
+
+        assert_eq!(injector.steal_batch(&worker), 2);
+        assert_eq!(injector.steal_batch(&worker), 0);
+
+        let first = injector.steal_batch_and_pop(&worker);
+        assert_eq!(first.success().map(|task| task.0), Some(2));
+        assert_eq!(worker.len(), 2);
+        assert_eq!(injector.len(), 7);
+    }
+
+    #[test]
+    fn test_skips_cancelled_tasks() {
+        let injector = Injector::<dyn Task>::new();
+        let (worker, _) = WorkStealingDeque::<dyn Task>::new(8);
+        let token = CancellationToken::new();
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let packet = Packet::new();
+                let token = (i % 2 == 0).then(|| token.clone());
+                injector.push(Box::new(Spawned::new(move || i, packet.clone(), token)));
+                JoinHandle::new(packet)
+            })
+            .collect();
+        token.cancel();
+
+        injector
+            .steal_batch_and_pop(&worker)
+            .success()
+            .unwrap()
+            .execute();
+        assert!(worker.is_empty());
+        injector.steal().success().unwrap().execute();
+        assert!(injector.steal().is_empty());
+
+        let results: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().ok())
+            .collect();
+        assert_eq!(results, [None, Some(1), None, Some(3)]);
+    }
+
+    #[test]
+    fn test_batch_skips_cancelled_tasks() {
+        let injector = Injector::<dyn Task>::new();
+        let (worker, _) = WorkStealingDeque::<dyn Task>::new(8);
+        let token = CancellationToken::new();
+        token.cancel();
+
+        worker.push(Box::new(Spawned::new(|| 10, Packet::new(), None)));
+        let handles: Vec<_> = (0..6)
+            .map(|i| {
+                let packet = Packet::new();
+                let token = (i == 0).then(|| token.clone());
+                injector.push(Box::new(Spawned::new(move || i, packet.clone(), token)));
+                JoinHandle::new(packet)
+            })
+            .collect();
+
+        // The next task of the batch stands in for the cancelled one, not
+        // the newest task in `worker`.
+        injector
+            .steal_batch_and_pop(&worker)
+            .success()
+            .unwrap()
+            .execute();
+        assert_eq!(worker.len(), 2);
+        worker.pop().success().unwrap().execute();
+        assert_eq!(injector.len(), 3);
+
+        let results: Vec<_> = handles
+            .into_iter()
+            .take(3)
+            .map(|handle| handle.join().ok())
+            .collect();
+        assert_eq!(results, [None, Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn test_concurrent_producers() {
+        let injector = Arc::new(Injector::new());
+
+        let producers: Vec<_> = (0..4)
+            .map(|_| {
+                let injector = injector.clone();
+                thread::spawn(move || {
+                    for i in 0..250 {
+                        injector.push(Box::new(TestTask(i)));
+                    }
+                })
+            })
+            .collect();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        assert_eq!(injector.len(), 1000);
+    }
+
+    #[test]
+    fn test_survives_poisoning() {
+        let injector = Arc::new(Injector::new());
+        injector.push(Box::new(TestTask(0)));
+
+        let poisoner = {
+            let injector = injector.clone();
+            thread::spawn(move || {
+                let _queue = injector.queue.lock().unwrap();
+                panic!("poison");
+            })
+        };
+        assert!(poisoner.join().is_err());
+
+        injector.push(Box::new(TestTask(1)));
+        assert_eq!(injector.len(), 2);
+        assert_eq!(injector.steal().success().map(|task| task.0), Some(0));
+    }
+}
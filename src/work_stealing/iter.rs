@@ -0,0 +1,949 @@
+//! Parallel iterators over slices and ranges.
+//!
+//! A parallel iterator is a `Producer` of items wrapped in adaptors. Calling
+//! a terminal operation such as `reduce` or `collect` builds a matching
+//! `Consumer` and splits both in half recursively with `join_context`, so
+//! the halves are handed out through the workers' deques. Work only runs in
+//! parallel on a worker thread, for example inside `Scheduler::install`;
+//! elsewhere everything is folded sequentially.
+
+use std::{collections::LinkedList, ops::Range};
+
+use super::{join::join_context, scheduler::WorkerThread};
+
+/// A source of items that can be split into two halves.
+pub trait Producer: Send + Sized {
+    type Item;
+    type IntoIter: Iterator<Item = Self::Item>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Splits into the first `index` items and the rest.
+    fn split_at(self, index: usize) -> (Self, Self);
+
+    fn into_iter(self) -> Self::IntoIter;
+}
+
+/// Receiver of the items of a parallel iterator, split alongside its
+/// producer.
+pub trait Consumer<Item>: Send + Sized {
+    type Folder: Folder<Item, Result = Self::Result>;
+    type Reducer: Reducer<Self::Result>;
+    type Result: Send;
+
+    /// Splits into consumers for the left and right half, plus the reducer
+    /// that combines their results in order.
+    fn split(self) -> (Self, Self, Self::Reducer);
+
+    fn into_folder(self) -> Self::Folder;
+}
+
+/// Sequential part of a `Consumer`.
+pub trait Folder<Item>: Sized {
+    type Result;
+
+    fn consume(self, item: Item) -> Self;
+
+    fn complete(self) -> Self::Result;
+
+    fn consume_iter<I>(self, iter: I) -> Self
+    where
+        I: IntoIterator<Item = Item>,
+    {
+        iter.into_iter().fold(self, Folder::consume)
+    }
+}
+
+pub trait Reducer<Result> {
+    fn reduce(self, left: Result, right: Result) -> Result;
+}
+
+pub trait ParallelIterator: Sized + Send {
+    type Item: Send;
+
+    /// Feeds every item into `consumer`.
+    fn drive<C>(self, consumer: C) -> C::Result
+    where
+        C: Consumer<Self::Item>;
+
+    fn map<F, R>(self, map_op: F) -> Map<Self, F>
+    where
+        F: Fn(Self::Item) -> R + Sync + Send,
+        R: Send,
+    {
+        Map { base: self, map_op }
+    }
+
+    fn filter<P>(self, predicate: P) -> Filter<Self, P>
+    where
+        P: Fn(&Self::Item) -> bool + Sync + Send,
+    {
+        Filter {
+            base: self,
+            predicate,
+        }
+    }
+
+    fn for_each<F>(self, op: F)
+    where
+        F: Fn(Self::Item) + Sync + Send,
+    {
+        self.drive(ForEachConsumer { op: &op })
+    }
+
+    /// Combines all items with `op`, starting every split from `identity`.
+    /// `op` must be associative for the result not to depend on how the
+    /// work was split.
+    fn reduce<ID, OP>(self, identity: ID, op: OP) -> Self::Item
+    where
+        ID: Fn() -> Self::Item + Sync + Send,
+        OP: Fn(Self::Item, Self::Item) -> Self::Item + Sync + Send,
+    {
+        self.drive(ReduceConsumer {
+            identity: &identity,
+            op: &op,
+        })
+    }
+
+    fn collect<C>(self) -> C
+    where
+        C: FromParallelIterator<Self::Item>,
+    {
+        C::from_par_iter(self)
+    }
+}
+
+pub trait FromParallelIterator<T>
+where
+    T: Send,
+{
+    fn from_par_iter<I>(iter: I) -> Self
+    where
+        I: ParallelIterator<Item = T>;
+}
+
+/// Keeps the items in order.
+impl<T> FromParallelIterator<T> for Vec<T>
+where
+    T: Send,
+{
+    fn from_par_iter<I>(iter: I) -> Self
+    where
+        I: ParallelIterator<Item = T>,
+    {
+        let chunks = iter.drive(CollectConsumer);
+        let mut result = Vec::with_capacity(chunks.iter().map(Vec::len).sum());
+
+        for mut chunk in chunks {
+            result.append(&mut chunk);
+        }
+
+        result
+    }
+}
+
+/// Conversion into a parallel iterator, implemented for ranges.
+pub trait IntoParallelIterator {
+    type Iter: ParallelIterator<Item = Self::Item>;
+    type Item: Send;
+
+    fn into_par_iter(self) -> Self::Iter;
+}
+
+/// Parallel iterators over the items of a slice.
+pub trait ParallelSlice<T>
+where
+    T: Sync,
+{
+    fn par_iter(&self) -> Iter<'_, T>;
+
+    /// Iterates over `chunk_size` items at a time. The last chunk may be
+    /// shorter.
+    fn par_chunks(&self, chunk_size: usize) -> Chunks<'_, T>;
+}
+
+impl<T> ParallelSlice<T> for [T]
+where
+    T: Sync,
+{
+    fn par_iter(&self) -> Iter<'_, T> {
+        Iter { slice: self }
+    }
+
+    fn par_chunks(&self, chunk_size: usize) -> Chunks<'_, T> {
+        assert!(chunk_size > 0, "chunk size must be positive");
+
+        Chunks {
+            slice: self,
+            chunk_size,
+        }
+    }
+}
+
+/// Decides how far to split. Starts with enough splits to give every
+/// worker a piece, halving on every split. A piece that was stolen means
+/// other workers are hungry, so it gets a fresh budget of splits.
+#[derive(Debug, Clone, Copy)]
+struct Splitter {
+    splits: usize,
+    threads: usize,
+}
+
+impl Splitter {
+    fn new(threads: usize) -> Self {
+        Self {
+            splits: threads,
+            threads,
+        }
+    }
+
+    fn try_split(&mut self, len: usize, migrated: bool) -> bool {
+        if len < 2 {
+            return false;
+        }
+
+        if migrated {
+            self.splits = self.threads.max(self.splits / 2);
+            true
+        } else if self.splits > 0 {
+            self.splits /= 2;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn bridge<P, C>(producer: P, consumer: C) -> C::Result
+where
+    P: Producer,
+    C: Consumer<P::Item>,
+{
+    // Off the pool there is nobody to steal the pieces.
+    let threads = WorkerThread::current().map_or(0, WorkerThread::num_threads);
+    let len = producer.len();
+
+    bridge_helper(len, false, Splitter::new(threads), producer, consumer)
+}
+
+fn bridge_helper<P, C>(
+    len: usize,
+    migrated: bool,
+    mut splitter: Splitter,
+    producer: P,
+    consumer: C,
+) -> C::Result
+where
+    P: Producer,
+    C: Consumer<P::Item>,
+{
+    if !splitter.try_split(len, migrated) {
+        return consumer
+            .into_folder()
+            .consume_iter(producer.into_iter())
+            .complete();
+    }
+
+    let mid = len / 2;
+    let (left_producer, right_producer) = producer.split_at(mid);
+    let (left_consumer, right_consumer, reducer) = consumer.split();
+
+    let (left, right) = join_context(
+        |ctx| bridge_helper(mid, ctx.migrated(), splitter, left_producer, left_consumer),
+        |ctx| {
+            bridge_helper(
+                len - mid,
+                ctx.migrated(),
+                splitter,
+                right_producer,
+                right_consumer,
+            )
+        },
+    );
+
+    reducer.reduce(left, right)
+}
+
+/// Parallel iterator over `&T` in a slice.
+#[derive(Debug)]
+pub struct Iter<'a, T> {
+    slice: &'a [T],
+}
+
+impl<'a, T> Producer for Iter<'a, T>
+where
+    T: Sync,
+{
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn len(&self) -> usize {
+        self.slice.len()
+    }
+
+    fn split_at(self, index: usize) -> (Self, Self) {
+        let (left, right) = self.slice.split_at(index);
+        (Iter { slice: left }, Iter { slice: right })
+    }
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.slice.iter()
+    }
+}
+
+impl<'a, T> ParallelIterator for Iter<'a, T>
+where
+    T: Sync,
+{
+    type Item = &'a T;
+
+    fn drive<C>(self, consumer: C) -> C::Result
+    where
+        C: Consumer<Self::Item>,
+    {
+        bridge(self, consumer)
+    }
+}
+
+/// Parallel iterator over chunks of a slice.
+#[derive(Debug)]
+pub struct Chunks<'a, T> {
+    slice: &'a [T],
+    chunk_size: usize,
+}
+
+impl<'a, T> Producer for Chunks<'a, T>
+where
+    T: Sync,
+{
+    type Item = &'a [T];
+    type IntoIter = std::slice::Chunks<'a, T>;
+
+    fn len(&self) -> usize {
+        self.slice.len().div_ceil(self.chunk_size)
+    }
+
+    fn split_at(self, index: usize) -> (Self, Self) {
+        let at = (index * self.chunk_size).min(self.slice.len());
+        let (left, right) = self.slice.split_at(at);
+        let chunk_size = self.chunk_size;
+
+        (
+            Chunks {
+                slice: left,
+                chunk_size,
+            },
+            Chunks {
+                slice: right,
+                chunk_size,
+            },
+        )
+    }
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.slice.chunks(self.chunk_size)
+    }
+}
+
+impl<'a, T> ParallelIterator for Chunks<'a, T>
+where
+    T: Sync,
+{
+    type Item = &'a [T];
+
+    fn drive<C>(self, consumer: C) -> C::Result
+    where
+        C: Consumer<Self::Item>,
+    {
+        bridge(self, consumer)
+    }
+}
+
+/// Parallel iterator over a range of integers.
+#[derive(Debug)]
+pub struct RangeIter<T> {
+    range: Range<T>,
+}
+
+macro_rules! range_iter {
+    ($($ty:ty),*) => {
+        $(
+            impl Producer for RangeIter<$ty> {
+                type Item = $ty;
+                type IntoIter = Range<$ty>;
+
+                fn len(&self) -> usize {
+                    if self.range.start < self.range.end {
+                        // The width of a signed range may not fit its type.
+                        usize::try_from(self.range.end.abs_diff(self.range.start))
+                            .expect("range is longer than usize::MAX")
+                    } else {
+                        0
+                    }
+                }
+
+                fn split_at(self, index: usize) -> (Self, Self) {
+                    // Neither can `index`, but wrapping lands on the right
+                    // value all the same.
+                    let mid = self.range.start.wrapping_add(index as $ty);
+
+                    (
+                        RangeIter { range: self.range.start..mid },
+                        RangeIter { range: mid..self.range.end },
+                    )
+                }
+
+                fn into_iter(self) -> Self::IntoIter {
+                    self.range
+                }
+            }
+
+            impl ParallelIterator for RangeIter<$ty> {
+                type Item = $ty;
+
+                fn drive<C>(self, consumer: C) -> C::Result
+                where
+                    C: Consumer<Self::Item>,
+                {
+                    bridge(self, consumer)
+                }
+            }
+
+            impl IntoParallelIterator for Range<$ty> {
+                type Iter = RangeIter<$ty>;
+                type Item = $ty;
+
+                fn into_par_iter(self) -> Self::Iter {
+                    RangeIter { range: self }
+                }
+            }
+        )*
+    };
+}
+
+range_iter!(usize, u32, u64, i32, i64);
+
+/// Parallel iterator returned by `ParallelIterator::map`.
+#[derive(Debug)]
+pub struct Map<I, F> {
+    base: I,
+    map_op: F,
+}
+
+impl<I, F, R> ParallelIterator for Map<I, F>
+where
+    I: ParallelIterator,
+    F: Fn(I::Item) -> R + Sync + Send,
+    R: Send,
+{
+    type Item = R;
+
+    fn drive<C>(self, consumer: C) -> C::Result
+    where
+        C: Consumer<Self::Item>,
+    {
+        self.base.drive(MapConsumer {
+            base: consumer,
+            map_op: &self.map_op,
+        })
+    }
+}
+
+struct MapConsumer<'f, C, F> {
+    base: C,
+    map_op: &'f F,
+}
+
+impl<'f, T, R, C, F> Consumer<T> for MapConsumer<'f, C, F>
+where
+    C: Consumer<R>,
+    F: Fn(T) -> R + Sync,
+{
+    type Folder = MapFolder<'f, C::Folder, F>;
+    type Reducer = C::Reducer;
+    type Result = C::Result;
+
+    fn split(self) -> (Self, Self, Self::Reducer) {
+        let (left, right, reducer) = self.base.split();
+        let map_op = self.map_op;
+
+        (
+            MapConsumer { base: left, map_op },
+            MapConsumer {
+                base: right,
+                map_op,
+            },
+            reducer,
+        )
+    }
+
+    fn into_folder(self) -> Self::Folder {
+        MapFolder {
+            base: self.base.into_folder(),
+            map_op: self.map_op,
+        }
+    }
+}
+
+struct MapFolder<'f, C, F> {
+    base: C,
+    map_op: &'f F,
+}
+
+impl<T, R, C, F> Folder<T> for MapFolder<'_, C, F>
+where
+    C: Folder<R>,
+    F: Fn(T) -> R,
+{
+    type Result = C::Result;
+
+    fn consume(self, item: T) -> Self {
+        MapFolder {
+            base: self.base.consume((self.map_op)(item)),
+            map_op: self.map_op,
+        }
+    }
+
+    fn complete(self) -> Self::Result {
+        self.base.complete()
+    }
+}
+
+/// Parallel iterator returned by `ParallelIterator::filter`.
+#[derive(Debug)]
+pub struct Filter<I, P> {
+    base: I,
+    predicate: P,
+}
+
+impl<I, P> ParallelIterator for Filter<I, P>
+where
+    I: ParallelIterator,
+    P: Fn(&I::Item) -> bool + Sync + Send,
+{
+    type Item = I::Item;
+
+    fn drive<C>(self, consumer: C) -> C::Result
+    where
+        C: Consumer<Self::Item>,
+    {
+        self.base.drive(FilterConsumer {
+            base: consumer,
+            predicate: &self.predicate,
+        })
+    }
+}
+
+struct FilterConsumer<'p, C, P> {
+    base: C,
+    predicate: &'p P,
+}
+
+impl<'p, T, C, P> Consumer<T> for FilterConsumer<'p, C, P>
+where
+    C: Consumer<T>,
+    P: Fn(&T) -> bool + Sync,
+{
+    type Folder = FilterFolder<'p, C::Folder, P>;
+    type Reducer = C::Reducer;
+    type Result = C::Result;
+
+    fn split(self) -> (Self, Self, Self::Reducer) {
+        let (left, right, reducer) = self.base.split();
+        let predicate = self.predicate;
+
+        (
+            FilterConsumer {
+                base: left,
+                predicate,
+            },
+            FilterConsumer {
+                base: right,
+                predicate,
+            },
+            reducer,
+        )
+    }
+
+    fn into_folder(self) -> Self::Folder {
+        FilterFolder {
+            base: self.base.into_folder(),
+            predicate: self.predicate,
+        }
+    }
+}
+
+struct FilterFolder<'p, C, P> {
+    base: C,
+    predicate: &'p P,
+}
+
+impl<T, C, P> Folder<T> for FilterFolder<'_, C, P>
+where
+    C: Folder<T>,
+    P: Fn(&T) -> bool,
+{
+    type Result = C::Result;
+
+    fn consume(self, item: T) -> Self {
+        if !(self.predicate)(&item) {
+            return self;
+        }
+
+        FilterFolder {
+            base: self.base.consume(item),
+            predicate: self.predicate,
+        }
+    }
+
+    fn complete(self) -> Self::Result {
+        self.base.complete()
+    }
+}
+
+struct NoopReducer;
+
+impl Reducer<()> for NoopReducer {
+    fn reduce(self, _left: (), _right: ()) {}
+}
+
+struct ForEachConsumer<'f, F> {
+    op: &'f F,
+}
+
+impl<T, F> Consumer<T> for ForEachConsumer<'_, F>
+where
+    F: Fn(T) + Sync,
+{
+    type Folder = Self;
+    type Reducer = NoopReducer;
+    type Result = ();
+
+    fn split(self) -> (Self, Self, Self::Reducer) {
+        (
+            ForEachConsumer { op: self.op },
+            ForEachConsumer { op: self.op },
+            NoopReducer,
+        )
+    }
+
+    fn into_folder(self) -> Self::Folder {
+        self
+    }
+}
+
+impl<T, F> Folder<T> for ForEachConsumer<'_, F>
+where
+    F: Fn(T),
+{
+    type Result = ();
+
+    fn consume(self, item: T) -> Self {
+        (self.op)(item);
+        self
+    }
+
+    fn complete(self) {}
+}
+
+struct ReduceConsumer<'f, ID, OP> {
+    identity: &'f ID,
+    op: &'f OP,
+}
+
+impl<'f, T, ID, OP> Consumer<T> for ReduceConsumer<'f, ID, OP>
+where
+    T: Send,
+    ID: Fn() -> T + Sync,
+    OP: Fn(T, T) -> T + Sync,
+{
+    type Folder = ReduceFolder<'f, T, OP>;
+    type Reducer = Self;
+    type Result = T;
+
+    fn split(self) -> (Self, Self, Self::Reducer) {
+        let (identity, op) = (self.identity, self.op);
+
+        (
+            ReduceConsumer { identity, op },
+            ReduceConsumer { identity, op },
+            self,
+        )
+    }
+
+    fn into_folder(self) -> Self::Folder {
+        ReduceFolder {
+            acc: (self.identity)(),
+            op: self.op,
+        }
+    }
+}
+
+impl<T, ID, OP> Reducer<T> for ReduceConsumer<'_, ID, OP>
+where
+    OP: Fn(T, T) -> T,
+{
+    fn reduce(self, left: T, right: T) -> T {
+        (self.op)(left, right)
+    }
+}
+
+struct ReduceFolder<'f, T, OP> {
+    acc: T,
+    op: &'f OP,
+}
+
+impl<T, OP> Folder<T> for ReduceFolder<'_, T, OP>
+where
+    OP: Fn(T, T) -> T,
+{
+    type Result = T;
+
+    fn consume(self, item: T) -> Self {
+        ReduceFolder {
+            acc: (self.op)(self.acc, item),
+            op: self.op,
+        }
+    }
+
+    fn complete(self) -> T {
+        self.acc
+    }
+}
+
+/// Collects each piece into a `Vec` and chains the pieces in order.
+struct CollectConsumer;
+
+struct ListAppend;
+
+impl<T> Reducer<LinkedList<T>> for ListAppend {
+    fn reduce(self, mut left: LinkedList<T>, mut right: LinkedList<T>) -> LinkedList<T> {
+        left.append(&mut right);
+        left
+    }
+}
+
+impl<T> Consumer<T> for CollectConsumer
+where
+    T: Send,
+{
+    type Folder = CollectFolder<T>;
+    type Reducer = ListAppend;
+    type Result = LinkedList<Vec<T>>;
+
+    fn split(self) -> (Self, Self, Self::Reducer) {
+        (CollectConsumer, CollectConsumer, ListAppend)
+    }
+
+    fn into_folder(self) -> Self::Folder {
+        CollectFolder { items: Vec::new() }
+    }
+}
+
+struct CollectFolder<T> {
+    items: Vec<T>,
+}
+
+impl<T> Folder<T> for CollectFolder<T> {
+    type Result = LinkedList<Vec<T>>;
+
+    fn consume(mut self, item: T) -> Self {
+        self.items.push(item);
+        self
+    }
+
+    fn complete(self) -> Self::Result {
+        let mut list = LinkedList::new();
+        list.push_back(self.items);
+        list
+    }
+}
+
+#[cfg(test)]
+mod iter_test {
+    use super::*;
+    use crate::work_stealing::scheduler::Scheduler;
+    use std::{
+        collections::HashSet,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+        thread,
+    };
+
+    #[test]
+    fn test_map_collect_keeps_order() {
+        let scheduler = Scheduler::new(4);
+        let values: Vec<u64> = (0..10_000).collect();
+
+        let doubled: Vec<u64> = scheduler.install(|| values.par_iter().map(|v| v * 2).collect());
+
+        assert_eq!(doubled, values.iter().map(|v| v * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_filter_reduce() {
+        let scheduler = Scheduler::new(3);
+
+        let sum = scheduler.install(|| {
+            (0..100_000u64)
+                .into_par_iter()
+                .filter(|v| v % 3 == 0)
+                .reduce(|| 0, |a, b| a + b)
+        });
+
+        assert_eq!(sum, (0..100_000u64).filter(|v| v % 3 == 0).sum());
+    }
+
+    #[test]
+    fn test_for_each_visits_every_item() {
+        let scheduler = Scheduler::new(2);
+        let seen = Mutex::new(HashSet::new());
+
+        scheduler.install(|| {
+            (0..1000usize).into_par_iter().for_each(|i| {
+                seen.lock().unwrap().insert(i);
+            })
+        });
+
+        assert_eq!(seen.into_inner().unwrap().len(), 1000);
+    }
+
+    #[test]
+    fn test_par_chunks() {
+        let scheduler = Scheduler::new(2);
+        let values: Vec<u32> = (0..1001).collect();
+
+        let sums: Vec<u32> = scheduler.install(|| {
+            values
+                .par_chunks(100)
+                .map(|chunk| chunk.iter().sum::<u32>())
+                .collect()
+        });
+
+        assert_eq!(sums.len(), 11);
+        assert_eq!(sums, values.chunks(100).map(|c| c.iter().sum()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_outside_pool_runs_sequentially() {
+        let values = [1, 2, 3, 4];
+        let threads = Mutex::new(HashSet::new());
+
+        let result: Vec<i32> = values
+            .par_iter()
+            .map(|v| {
+                threads.lock().unwrap().insert(thread::current().id());
+                v * 10
+            })
+            .collect();
+
+        assert_eq!(result, vec![10, 20, 30, 40]);
+        assert_eq!(threads.into_inner().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_empty_inputs() {
+        let scheduler = Scheduler::new(2);
+        let values: Vec<u8> = Vec::new();
+
+        let collected: Vec<u8> = scheduler.install(|| values.par_iter().map(|v| *v).collect());
+        let sum = scheduler.install(|| (5..5i32).into_par_iter().reduce(|| 0, |a, b| a + b));
+
+        assert!(collected.is_empty());
+        assert_eq!(sum, 0);
+    }
+
+    #[test]
+    fn test_full_width_ranges() {
+        let range = RangeIter {
+            range: i32::MIN..i32::MAX,
+        };
+        assert_eq!(range.len(), u32::MAX as usize);
+
+        let (left, right) = range.split_at(u32::MAX as usize - 1);
+        assert_eq!(left.range, i32::MIN..i32::MAX - 1);
+        assert_eq!(right.range, i32::MAX - 1..i32::MAX);
+
+        #[cfg(target_pointer_width = "64")]
+        {
+            let range = RangeIter {
+                range: i64::MIN..i64::MAX,
+            };
+            assert_eq!(range.len(), u64::MAX as usize);
+        }
+
+        let scheduler = Scheduler::new(2);
+        let sum = scheduler.install(|| {
+            (i32::MIN..i32::MIN + 10_000)
+                .into_par_iter()
+                .map(i64::from)
+                .reduce(|| 0, |a, b| a + b)
+        });
+        assert_eq!(sum, (i32::MIN..i32::MIN + 10_000).map(i64::from).sum());
+    }
+
+    #[test]
+    fn test_splitter_only_splits_on_steals() {
+        let mut splitter = Splitter::new(4);
+
+        // Without steals the budget runs out after a few splits.
+        assert!(splitter.try_split(1000, false));
+        assert!(splitter.try_split(500, false));
+        assert!(splitter.try_split(250, false));
+        assert!(!splitter.try_split(125, false));
+
+        // A stolen piece gets a new budget.
+        assert!(splitter.try_split(125, true));
+        assert_eq!(splitter.splits, 4);
+        assert!(!splitter.try_split(1, true));
+    }
+
+    #[test]
+    fn test_pieces_stay_coarse_without_thieves() {
+        let scheduler = Scheduler::new(1);
+        let pieces = AtomicUsize::new(0);
+
+        scheduler.install(|| {
+            (0..100_000usize)
+                .into_par_iter()
+                .map(|_| 1)
+                .drive(CountPieces(&pieces))
+        });
+
+        // One worker never steals, so only the initial budget is spent.
+        assert_eq!(pieces.load(Ordering::Relaxed), 2);
+    }
+
+    struct CountPieces<'a>(&'a AtomicUsize);
+
+    impl<T> Consumer<T> for CountPieces<'_> {
+        type Folder = Self;
+        type Reducer = NoopReducer;
+        type Result = ();
+
+        fn split(self) -> (Self, Self, Self::Reducer) {
+            (CountPieces(self.0), CountPieces(self.0), NoopReducer)
+        }
+
+        fn into_folder(self) -> Self::Folder {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            self
+        }
+    }
+
+    impl<T> Folder<T> for CountPieces<'_> {
+        type Result = ();
+
+        fn consume(self, _item: T) -> Self {
+            self
+        }
+
+        fn complete(self) {}
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    cell::UnsafeCell,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Condvar, Mutex,
+    },
+    task::Waker,
+    thread,
+};
+
+use super::{schedule::Task, sleep::Sleep};
+
+/// Signals that a job has finished. Once `set` returns, the job that owns
+/// the latch may already be gone, so `set` must be the last thing a job
+/// touches.
+pub(crate) trait Latch {
+    fn set(&self);
+}
+
+/// Latch polled by a worker that keeps busy while it waits, and parks on
+/// `sleep` once there is nothing else to do.
+pub(crate) struct SpinLatch<'s> {
+    done: AtomicBool,
+    sleep: &'s Sleep,
+}
+
+impl<'s> SpinLatch<'s> {
+    pub(crate) fn new(sleep: &'s Sleep) -> Self {
+        Self {
+            done: AtomicBool::new(false),
+            sleep,
+        }
+    }
+
+    pub(crate) fn probe(&self) -> bool {
+        self.done.load(Ordering::Acquire)
+    }
+}
+
+impl Latch for SpinLatch<'_> {
+    fn set(&self) {
+        // The scheduler outlives the latch, which may go away as soon as
+        // `done` is stored.
+        let sleep = self.sleep;
+        self.done.store(true, Ordering::Release);
+        sleep.notify_all();
+    }
+}
+
+/// Latch for threads outside the pool, which block until it is set. A
+/// worker of another pool waits on it too, and registers a `Waker` to be
+/// woken.
+pub(crate) struct LockLatch {
+    done: Mutex<bool>,
+    cond: Condvar,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl LockLatch {
+    pub(crate) fn new() -> Self {
+        Self {
+            done: Mutex::new(false),
+            cond: Condvar::new(),
+            waker: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn register(&self, waker: Waker) {
+        *self.waker.lock().unwrap() = Some(waker);
+    }
+
+    pub(crate) fn probe(&self) -> bool {
+        *self.done.lock().unwrap()
+    }
+
+    pub(crate) fn wait(&self) {
+        let mut done = self.done.lock().unwrap();
+        while !*done {
+            done = self.cond.wait(done).unwrap();
+        }
+    }
+}
+
+impl Latch for LockLatch {
+    fn set(&self) {
+        // A waker registered once this took it is followed by a look at
+        // `done`, which sees it set. Until the lock is released the waiter
+        // cannot see that, so the latch is still around.
+        let mut done = self.done.lock().unwrap();
+        *done = true;
+        let waker = self.waker.lock().unwrap().take();
+        self.cond.notify_all();
+        drop(done);
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// A job that lives on the stack of the thread waiting for it, so it can
+/// borrow from that stack. The waiting thread must not return before the
+/// job has either run or been taken back.
+pub(crate) struct StackJob<L, F, R> {
+    pub(crate) latch: L,
+    func: UnsafeCell<Option<F>>,
+    result: UnsafeCell<Option<thread::Result<R>>>,
+}
+
+impl<L, F, R> StackJob<L, F, R>
+where
+    L: Latch,
+    F: FnOnce() -> R + Send,
+    R: Send,
+{
+    pub(crate) fn new(func: F, latch: L) -> Self {
+        Self {
+            latch,
+            func: UnsafeCell::new(Some(func)),
+            result: UnsafeCell::new(None),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The job must stay in place until its latch is set or the returned
+    /// reference has been dropped without running.
+    pub(crate) unsafe fn as_job_ref(&self) -> JobRef {
+        JobRef {
+            pointer: self as *const Self as *const (),
+            execute_fn: Self::execute,
+        }
+    }
+
+    unsafe fn execute(this: *const ()) {
+        let this = &*(this as *const Self);
+        let func = (*this.func.get()).take().unwrap();
+
+        *this.result.get() = Some(panic::catch_unwind(AssertUnwindSafe(func)));
+        this.latch.set();
+    }
+
+    /// Runs the job on the current thread after taking it back unrun.
+    pub(crate) fn run_inline(self) -> R {
+        self.func.into_inner().unwrap()()
+    }
+
+    pub(crate) fn into_result(self) -> thread::Result<R> {
+        self.result.into_inner().unwrap()
+    }
+}
+
+/// Type-erased pointer to a `StackJob`, which is what actually goes into a
+/// deque.
+pub(crate) struct JobRef {
+    pointer: *const (),
+    execute_fn: unsafe fn(*const ()),
+}
+
+// The job behind the pointer only holds `Send` closures and results.
+unsafe impl Send for JobRef {}
+
+impl Task for JobRef {
+    fn execute(self: Box<Self>) {
+        unsafe { (self.execute_fn)(self.pointer) }
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    thread,
+};
+
+use super::{
+    job::{SpinLatch, StackJob},
+    scheduler::{self, WorkerThread},
+};
+
+/// Runs `a` and `b`, potentially in parallel, and returns both results.
+///
+/// On a worker thread, `b` is pushed onto the worker's deque and `a` runs
+/// right away. Afterwards the worker pops `b` back and runs it itself
+/// unless a thief took it first, in which case it runs other tasks until
+/// the thief is done. Outside of a pool both closures simply run one after
+/// the other; use `Scheduler::install` to get onto one.
+///
+/// If either closure panics, the other one still runs, and the panic is
+/// resumed once both are finished. If both panic, `a`'s panic wins.
+pub fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    join_context(|_| a(), |_| b())
+}
+
+/// Tells a closure run by `join_context` where it ended up running.
+#[derive(Debug, Clone, Copy)]
+pub struct FnContext {
+    migrated: bool,
+}
+
+impl FnContext {
+    /// Returns `true` if the closure was stolen and runs on a different
+    /// worker than the one that called `join_context`.
+    pub fn migrated(&self) -> bool {
+        self.migrated
+    }
+}
+
+/// Like `join`, but passes each closure a `FnContext`. Code that splits
+/// work recursively can use it to only keep splitting while thieves are
+/// actually picking up the pieces.
+pub fn join_context<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce(FnContext) -> RA + Send,
+    B: FnOnce(FnContext) -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    let context = FnContext { migrated: false };
+
+    let Some(worker) = WorkerThread::current() else {
+        let result_a = panic::catch_unwind(AssertUnwindSafe(|| a(context)));
+        let result_b = panic::catch_unwind(AssertUnwindSafe(|| b(context)));
+        return unwrap_both(result_a, result_b);
+    };
+
+    let home = worker.index();
+    let priority = worker.priority();
+    let job_b = StackJob::new(
+        move || {
+            let migrated = WorkerThread::current().is_none_or(|thief| thief.index() != home);
+            b(FnContext { migrated })
+        },
+        SpinLatch::new(worker.sleep()),
+    );
+    let job_b_ref: Box<scheduler::Job> = Box::new(unsafe { job_b.as_job_ref() });
+    let job_b_id = job_id(&*job_b_ref);
+    worker.push(job_b_ref, priority);
+
+    let result_a = panic::catch_unwind(AssertUnwindSafe(|| a(context)));
+
+    while !job_b.latch.probe() {
+        match worker.pop(priority) {
+            Some(job) if job_id(&*job) == job_b_id => {
+                // Nobody stole `b`, so it runs right here, whether `a`
+                // panicked or not.
+                drop(job);
+                let result_b = panic::catch_unwind(AssertUnwindSafe(move || job_b.run_inline()));
+                return unwrap_both(result_a, result_b);
+            }
+            Some(job) => worker.execute(job, priority),
+            None => worker.wait_until(|| job_b.latch.probe()),
+        }
+    }
+
+    unwrap_both(result_a, job_b.into_result())
+}
+
+/// Both results, or the panic of `a`, failing that of `b`, resumed.
+fn unwrap_both<RA, RB>(result_a: thread::Result<RA>, result_b: thread::Result<RB>) -> (RA, RB) {
+    match (result_a, result_b) {
+        (Ok(result_a), Ok(result_b)) => (result_a, result_b),
+        (Err(payload), _) | (_, Err(payload)) => panic::resume_unwind(payload),
+    }
+}
+
+fn job_id(job: &scheduler::Job) -> *const () {
+    job as *const scheduler::Job as *const ()
+}
+
+#[cfg(test)]
+mod join_test {
+    use super::*;
+    use crate::work_stealing::scheduler::Scheduler;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+
+    fn fib(n: u64) -> u64 {
+        if n < 2 {
+            return n;
+        }
+
+        let (a, b) = join(|| fib(n - 1), || fib(n - 2));
+        a + b
+    }
+
+    fn sum(values: &[u64]) -> u64 {
+        if values.len() <= 16 {
+            return values.iter().sum();
+        }
+
+        let (left, right) = values.split_at(values.len() / 2);
+        let (a, b) = join(|| sum(left), || sum(right));
+        a + b
+    }
+
+    #[test]
+    fn test_join_outside_pool() {
+        assert_eq!(join(|| 1, || "two"), (1, "two"));
+        assert_eq!(fib(10), 55);
+    }
+
+    #[test]
+    fn test_fib_on_pool() {
+        let scheduler = Scheduler::new(4);
+
+        assert_eq!(scheduler.install(|| fib(20)), 6765);
+    }
+
+    #[test]
+    fn test_join_borrows_from_stack() {
+        let scheduler = Scheduler::new(3);
+        let values: Vec<u64> = (0..10_000).collect();
+
+        assert_eq!(scheduler.install(|| sum(&values)), 49_995_000);
+    }
+
+    #[test]
+    fn test_join_runs_each_side_once() {
+        let scheduler = Scheduler::new(2);
+        let ran_a = AtomicUsize::new(0);
+        let ran_b = AtomicUsize::new(0);
+
+        scheduler.install(|| {
+            for _ in 0..100 {
+                // Yielding gives the other worker a chance to steal `b`, so
+                // both the stolen and the popped-back paths get exercised.
+                join(
+                    || {
+                        ran_a.fetch_add(1, Ordering::Relaxed);
+                        thread::yield_now();
+                    },
+                    || {
+                        ran_b.fetch_add(1, Ordering::Relaxed);
+                        thread::yield_now();
+                    },
+                );
+            }
+        });
+
+        assert_eq!(ran_a.load(Ordering::Relaxed), 100);
+        assert_eq!(ran_b.load(Ordering::Relaxed), 100);
+    }
+
+    #[test]
+    fn test_join_context_outside_pool() {
+        let (a, b) = join_context(|ctx| ctx.migrated(), |ctx| ctx.migrated());
+
+        assert!(!a && !b);
+    }
+
+    #[test]
+    fn test_join_context_reports_migration() {
+        let scheduler = Scheduler::new(2);
+
+        for _ in 0..50 {
+            let ((a_migrated, a_thread), (b_migrated, b_thread)) = scheduler.install(|| {
+                join_context(
+                    |ctx| {
+                        thread::yield_now();
+                        (ctx.migrated(), thread::current().id())
+                    },
+                    |ctx| (ctx.migrated(), thread::current().id()),
+                )
+            });
+
+            assert!(!a_migrated);
+            assert_eq!(b_migrated, a_thread != b_thread);
+        }
+    }
+
+    #[test]
+    fn test_join_propagates_panic() {
+        let scheduler = Scheduler::new(2);
+        let ran_a = AtomicUsize::new(0);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            scheduler.install(|| {
+                join(
+                    || ran_a.fetch_add(1, Ordering::Relaxed),
+                    || -> usize { panic!("b failed") },
+                )
+            })
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(ran_a.load(Ordering::Relaxed), 1);
+        assert_eq!(scheduler.install(|| fib(10)), 55);
+    }
+
+    #[test]
+    fn test_panic_in_a_still_runs_b() {
+        // With a single worker nobody can steal `b`, so it is popped back.
+        let scheduler = Scheduler::new(1);
+        let ran_b = AtomicUsize::new(0);
+        let run = || {
+            join(
+                || -> usize { panic!("a failed") },
+                || ran_b.fetch_add(1, Ordering::Relaxed),
+            )
+        };
+
+        let on_pool = panic::catch_unwind(AssertUnwindSafe(|| scheduler.install(run)));
+        let off_pool = panic::catch_unwind(AssertUnwindSafe(run));
+
+        assert!(on_pool.is_err() && off_pool.is_err());
+        assert_eq!(ran_b.load(Ordering::Relaxed), 2);
+    }
+}
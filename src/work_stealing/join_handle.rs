@@ -0,0 +1,256 @@
+use std::{
+    error::Error,
+    fmt,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use super::{
+    cancel::{CancellationToken, Cancelled},
+    schedule::Task,
+    scheduler,
+};
+
+struct PacketState<R> {
+    result: Option<thread::Result<R>>,
+    waker: Option<Waker>,
+}
+
+/// Slot a spawned task writes its outcome into.
+pub(crate) struct Packet<R> {
+    state: Mutex<PacketState<R>>,
+    done: Condvar,
+}
+
+impl<R> Packet<R> {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(PacketState {
+                result: None,
+                waker: None,
+            }),
+            done: Condvar::new(),
+        })
+    }
+
+    pub(crate) fn complete(&self, result: thread::Result<R>) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            state.result = Some(result);
+            state.waker.take()
+        };
+
+        self.done.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.state.lock().unwrap().result.is_some()
+    }
+}
+
+/// Completes its packet with an `Abandoned` payload if it is dropped
+/// before `complete` is called.
+pub(crate) struct PacketGuard<R> {
+    packet: Option<Arc<Packet<R>>>,
+}
+
+impl<R> PacketGuard<R> {
+    pub(crate) fn new(packet: Arc<Packet<R>>) -> Self {
+        Self {
+            packet: Some(packet),
+        }
+    }
+
+    pub(crate) fn complete(mut self, result: thread::Result<R>) {
+        if let Some(packet) = self.packet.take() {
+            packet.complete(result);
+        }
+    }
+}
+
+impl<R> Drop for PacketGuard<R> {
+    fn drop(&mut self) {
+        if let Some(packet) = self.packet.take() {
+            packet.complete(Err(Box::new(Abandoned)));
+        }
+    }
+}
+
+/// The task behind a `JoinHandle`: runs `f` and stores its outcome. One
+/// that is skipped because its token was cancelled stores `Cancelled`, and
+/// one that is dropped without running stores `Abandoned`, so `join` never
+/// waits for a task that is gone.
+pub(crate) struct Spawned<F, R> {
+    f: F,
+    packet: PacketGuard<R>,
+    token: Option<CancellationToken>,
+}
+
+impl<F, R> Spawned<F, R> {
+    pub(crate) fn new(f: F, packet: Arc<Packet<R>>, token: Option<CancellationToken>) -> Self {
+        Self {
+            f,
+            packet: PacketGuard::new(packet),
+            token,
+        }
+    }
+}
+
+impl<F, R> Task for Spawned<F, R>
+where
+    F: FnOnce() -> R,
+{
+    fn execute(self: Box<Self>) {
+        let Spawned { f, packet, .. } = *self;
+        packet.complete(panic::catch_unwind(AssertUnwindSafe(f)));
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.token
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    fn cancel(self: Box<Self>) {
+        self.packet.complete(Err(Box::new(Cancelled)));
+    }
+}
+
+/// Error for a spawned task that was dropped without ever running, or a
+/// spawned future that was dropped before it finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Abandoned;
+
+impl fmt::Display for Abandoned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("task dropped before it finished")
+    }
+}
+
+impl Error for Abandoned {}
+
+/// Owned permission to wait for a spawned task and take its result.
+///
+/// A handle can be waited on with `join`, polled with `try_join`, or
+/// awaited from async code. Dropping it detaches the task, which still runs
+/// to completion.
+pub struct JoinHandle<R> {
+    packet: Arc<Packet<R>>,
+}
+
+impl<R> JoinHandle<R> {
+    pub(crate) fn new(packet: Arc<Packet<R>>) -> Self {
+        Self { packet }
+    }
+
+    /// Returns `true` once the task has finished, whether it returned or
+    /// panicked.
+    pub fn is_finished(&self) -> bool {
+        self.packet.is_complete()
+    }
+
+    /// Waits for the task and returns its value, or the payload it
+    /// panicked with.
+    ///
+    /// On a worker thread the worker keeps running other tasks while it
+    /// waits, so joining from inside the pool cannot deadlock it.
+    pub fn join(self) -> thread::Result<R> {
+        let register = |waker| self.packet.state.lock().unwrap().waker = Some(waker);
+        if scheduler::help_until(|| self.packet.is_complete(), register) {
+            return self.take();
+        }
+
+        let mut state = self.packet.state.lock().unwrap();
+        loop {
+            if let Some(result) = state.result.take() {
+                return result;
+            }
+            state = self.packet.done.wait(state).unwrap();
+        }
+    }
+
+    /// Returns the result if the task has finished, or the handle back if
+    /// it is still running.
+    pub fn try_join(self) -> Result<thread::Result<R>, Self> {
+        if self.is_finished() {
+            Ok(self.take())
+        } else {
+            Err(self)
+        }
+    }
+
+    fn take(self) -> thread::Result<R> {
+        self.packet.state.lock().unwrap().result.take().unwrap()
+    }
+}
+
+impl<R> Future for JoinHandle<R> {
+    type Output = thread::Result<R>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.packet.state.lock().unwrap();
+
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<R> fmt::Debug for JoinHandle<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod join_handle_test {
+    use super::*;
+
+    #[test]
+    fn test_try_join() {
+        let packet = Packet::new();
+        let handle = JoinHandle::new(packet.clone());
+
+        assert!(!handle.is_finished());
+        let handle = handle.try_join().unwrap_err();
+
+        packet.complete(Ok(5));
+        assert!(handle.is_finished());
+        assert_eq!(handle.try_join().ok().map(Result::unwrap), Some(5));
+    }
+
+    #[test]
+    fn test_join_blocks_until_complete() {
+        let packet = Packet::new();
+        let handle = JoinHandle::new(packet.clone());
+
+        let completer = thread::spawn(move || packet.complete(Ok("done")));
+
+        assert_eq!(handle.join().unwrap(), "done");
+        completer.join().unwrap();
+    }
+
+    #[test]
+    fn test_dropped_task_completes_packet() {
+        let packet = Packet::<i32>::new();
+        let handle = JoinHandle::new(packet.clone());
+
+        drop(Spawned::new(|| 1, packet, None));
+
+        let payload = handle.join().unwrap_err();
+        assert_eq!(payload.downcast_ref::<Abandoned>(), Some(&Abandoned));
+    }
+}
@@ -0,0 +1,476 @@
+//! Linearizability checking for `WorkStealingDeque`.
+//!
+//! Random workloads run on real threads while every call is recorded
+//! together with the logical times it started and returned. A history is
+//! linearizable if its calls can be put in an order that respects those
+//! times and in which a sequential deque returns exactly what the real one
+//! did. `is_linearizable` searches for such an order the way Wing and Gong
+//! do, skipping states it has already ruled out as Lowe suggests. A failing
+//! workload is shrunk by dropping threads and calls for as long as the
+//! smaller workload still fails. The report has the seed the workloads were
+//! drawn from, which `MEMO_LIN_SEED` takes to draw them again.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    env, fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Barrier,
+    },
+    thread,
+};
+
+use super::{
+    backend::Backend,
+    rng::XorShift,
+    schedule::{Steal, Task, WorkStealingDeque},
+};
+
+/// How often a workload is rerun before it counts as passing. Threads
+/// interleave differently on every run, so one clean run proves little.
+const RUNS: usize = 20;
+
+/// Upper bound on the calls in a generated workload; the search keeps the
+/// finished calls in a `u64` bitmask.
+const MAX_CALLS: usize = 24;
+
+/// Environment variable `seed_from_env` reads the seed from.
+const SEED_VAR: &str = "MEMO_LIN_SEED";
+
+struct Item(u32);
+
+impl Task for Item {
+    fn execute(self: Box<Self>) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Push(u32),
+    Pop,
+    Steal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Pushed,
+    Took(Option<u32>),
+    /// A steal that lost a race. It changes nothing, so it fits anywhere.
+    Retry,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Call {
+    thread: usize,
+    op: Op,
+    outcome: Outcome,
+    invoked: u64,
+    returned: u64,
+}
+
+/// Calls every thread makes, in order. Thread 0 owns the deque and only
+/// pushes and pops; every other thread only steals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Workload {
+    threads: Vec<Vec<Op>>,
+}
+
+impl Workload {
+    fn generate(rng: &mut XorShift) -> Self {
+        let mut next_value = 0;
+        let mut owner = Vec::new();
+
+        for _ in 0..1 + rng.next_usize(12) {
+            if rng.next_usize(3) < 2 {
+                owner.push(Op::Push(next_value));
+                next_value += 1;
+            } else {
+                owner.push(Op::Pop);
+            }
+        }
+
+        let mut threads = vec![owner];
+        for _ in 0..rng.next_usize(4) {
+            threads.push(vec![Op::Steal; 1 + rng.next_usize(4)]);
+        }
+
+        Self { threads }
+    }
+
+    fn len(&self) -> usize {
+        self.threads.iter().map(Vec::len).sum()
+    }
+
+    /// Workloads with one thief or one call less, the thieves first.
+    fn shrink(&self) -> Vec<Workload> {
+        let mut smaller = Vec::new();
+
+        for thread in 1..self.threads.len() {
+            let mut threads = self.threads.clone();
+            threads.remove(thread);
+            smaller.push(Self { threads });
+        }
+
+        for (thread, ops) in self.threads.iter().enumerate() {
+            for op in 0..ops.len() {
+                let mut threads = self.threads.clone();
+                threads[thread].remove(op);
+                smaller.push(Self { threads });
+            }
+        }
+
+        smaller
+    }
+
+    /// Runs the workload once on `B` and returns what happened.
+    fn run<B>(&self) -> Vec<Call>
+    where
+        B: Backend<Item> + Send + Sync,
+    {
+        let (worker, stealer) = WorkStealingDeque::<Item, B>::new(4);
+        let clock = AtomicU64::new(0);
+        let start = Barrier::new(self.threads.len());
+        let tick = || clock.fetch_add(1, Ordering::SeqCst);
+
+        thread::scope(|scope| {
+            let (owner, thieves) = self.threads.split_first().unwrap();
+
+            let thieves: Vec<_> = thieves
+                .iter()
+                .enumerate()
+                .map(|(index, ops)| {
+                    let stealer = stealer.clone();
+                    let (start, tick) = (&start, &tick);
+
+                    scope.spawn(move || {
+                        start.wait();
+                        ops.iter()
+                            .map(|&op| {
+                                let invoked = tick();
+                                let outcome = match stealer.steal() {
+                                    Steal::Success(item) => Outcome::Took(Some(item.0)),
+                                    Steal::Empty => Outcome::Took(None),
+                                    Steal::Retry => Outcome::Retry,
+                                };
+                                let returned = tick();
+
+                                Call {
+                                    thread: index + 1,
+                                    op,
+                                    outcome,
+                                    invoked,
+                                    returned,
+                                }
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            start.wait();
+            let mut history: Vec<Call> = owner
+                .iter()
+                .map(|&op| {
+                    let invoked = tick();
+                    let outcome = match op {
+                        Op::Push(value) => {
+                            worker.push(Box::new(Item(value)));
+                            Outcome::Pushed
+                        }
+                        _ => Outcome::Took(worker.pop().success().map(|item| item.0)),
+                    };
+                    let returned = tick();
+
+                    Call {
+                        thread: 0,
+                        op,
+                        outcome,
+                        invoked,
+                        returned,
+                    }
+                })
+                .collect();
+
+            for thief in thieves {
+                history.extend(thief.join().unwrap());
+            }
+            history
+        })
+    }
+
+    /// Runs the workload up to `RUNS` times and returns the first history
+    /// that is not linearizable.
+    fn find_violation<B>(&self) -> Option<Vec<Call>>
+    where
+        B: Backend<Item> + Send + Sync,
+    {
+        (0..RUNS)
+            .map(|_| self.run::<B>())
+            .find(|history| !is_linearizable(history))
+    }
+}
+
+/// Applies `call` to the sequential deque, unless the outcome it recorded
+/// is impossible in `state`.
+fn replay(state: &mut VecDeque<u32>, call: &Call) -> bool {
+    let expected = match (call.op, call.outcome) {
+        (Op::Steal, Outcome::Retry) => return true,
+        (Op::Push(value), _) => {
+            state.push_back(value);
+            Outcome::Pushed
+        }
+        (Op::Pop, _) => Outcome::Took(state.pop_back()),
+        (Op::Steal, _) => Outcome::Took(state.pop_front()),
+    };
+
+    expected == call.outcome
+}
+
+fn is_linearizable(history: &[Call]) -> bool {
+    assert!(history.len() <= u64::BITS as usize, "history too long");
+
+    search(history, 0, VecDeque::new(), &mut HashSet::new())
+}
+
+/// Tries every call that may come next after the calls in `done`.
+fn search(
+    history: &[Call],
+    done: u64,
+    state: VecDeque<u32>,
+    seen: &mut HashSet<(u64, VecDeque<u32>)>,
+) -> bool {
+    let pending = || (0..history.len()).filter(move |&index| done & 1 << index == 0);
+
+    // A call can only come next if it started before every pending call
+    // returned, its own return included.
+    let Some(deadline) = pending().map(|index| history[index].returned).min() else {
+        return true;
+    };
+    if !seen.insert((done, state.clone())) {
+        return false;
+    }
+
+    pending()
+        .filter(|&index| history[index].invoked < deadline)
+        .any(|index| {
+            let mut next = state.clone();
+            replay(&mut next, &history[index]) && search(history, done | 1 << index, next, seen)
+        })
+}
+
+/// A workload that broke linearizability, shrunk as far as it would go.
+struct Failure {
+    seed: u64,
+    workload: Workload,
+    history: Vec<Call>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "history is not linearizable (seed {0}, rerun with {1}={0})",
+            self.seed, SEED_VAR
+        )?;
+        writeln!(f, "workload: {:?}", self.workload.threads)?;
+
+        let mut history = self.history.clone();
+        history.sort_by_key(|call| call.invoked);
+        for call in history {
+            writeln!(
+                f,
+                "  [{:>3}, {:>3}] thread {}: {:?} -> {:?}",
+                call.invoked, call.returned, call.thread, call.op, call.outcome
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Seed from the `MEMO_LIN_SEED` environment variable, to replay a failed
+/// check, or a random one.
+///
+/// # Panics
+///
+/// If the variable is set but not a `u64`.
+fn seed_from_env() -> u64 {
+    match env::var(SEED_VAR) {
+        Ok(seed) => seed
+            .trim()
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be a u64, got {:?}", SEED_VAR, seed)),
+        Err(_) => XorShift::from_entropy().next_u64(),
+    }
+}
+
+/// Checks `cases` random workloads on `B`, shrinking the first one that
+/// fails.
+fn check<B>(seed: u64, cases: usize) -> Result<(), Failure>
+where
+    B: Backend<Item> + Send + Sync,
+{
+    let mut rng = XorShift::new(seed);
+
+    for _ in 0..cases {
+        let workload = Workload::generate(&mut rng);
+        debug_assert!(workload.len() <= MAX_CALLS);
+
+        if let Some(history) = workload.find_violation::<B>() {
+            let (workload, history) = shrink::<B>(workload, history);
+            return Err(Failure {
+                seed,
+                workload,
+                history,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn shrink<B>(mut workload: Workload, mut history: Vec<Call>) -> (Workload, Vec<Call>)
+where
+    B: Backend<Item> + Send + Sync,
+{
+    'shrink: loop {
+        for smaller in workload.shrink() {
+            if let Some(violation) = smaller.find_violation::<B>() {
+                workload = smaller;
+                history = violation;
+                continue 'shrink;
+            }
+        }
+
+        return (workload, history);
+    }
+}
+
+#[cfg(test)]
+mod linearizability_test {
+    use super::*;
+    use crate::work_stealing::{backend::MutexBuffer, chase_lev::ChaseLev};
+
+    fn call(thread: usize, op: Op, outcome: Outcome, invoked: u64, returned: u64) -> Call {
+        Call {
+            thread,
+            op,
+            outcome,
+            invoked,
+            returned,
+        }
+    }
+
+    #[test]
+    fn test_sequential_histories() {
+        let push = |value, at| call(0, Op::Push(value), Outcome::Pushed, at, at + 1);
+
+        let fifo_steal = [
+            push(1, 0),
+            push(2, 2),
+            call(1, Op::Steal, Outcome::Took(Some(1)), 4, 5),
+            call(0, Op::Pop, Outcome::Took(Some(2)), 6, 7),
+        ];
+        assert!(is_linearizable(&fifo_steal));
+
+        let lifo_steal = [
+            push(1, 0),
+            push(2, 2),
+            call(1, Op::Steal, Outcome::Took(Some(2)), 4, 5),
+        ];
+        assert!(!is_linearizable(&lifo_steal));
+
+        let lost = [push(1, 0), call(0, Op::Pop, Outcome::Took(None), 2, 3)];
+        assert!(!is_linearizable(&lost));
+    }
+
+    #[test]
+    fn test_overlapping_calls() {
+        // The steal overlaps both the push and the pop, so it may have
+        // taken the task before the pop looked.
+        let history = [
+            call(0, Op::Push(1), Outcome::Pushed, 0, 2),
+            call(1, Op::Steal, Outcome::Took(Some(1)), 1, 5),
+            call(0, Op::Pop, Outcome::Took(None), 3, 4),
+            call(1, Op::Steal, Outcome::Retry, 6, 7),
+        ];
+        assert!(is_linearizable(&history));
+
+        // Both got the one task: no order explains that.
+        let duplicated = [
+            call(0, Op::Push(1), Outcome::Pushed, 0, 1),
+            call(1, Op::Steal, Outcome::Took(Some(1)), 2, 5),
+            call(0, Op::Pop, Outcome::Took(Some(1)), 3, 4),
+        ];
+        assert!(!is_linearizable(&duplicated));
+    }
+
+    #[test]
+    fn test_shrink_candidates() {
+        let workload = Workload {
+            threads: vec![vec![Op::Push(0), Op::Pop], vec![Op::Steal]],
+        };
+        let smaller = workload.shrink();
+
+        assert_eq!(smaller.len(), 4);
+        assert_eq!(smaller[0].threads, vec![vec![Op::Push(0), Op::Pop]]);
+        assert!(smaller.iter().all(|smaller| smaller.len() < workload.len()));
+    }
+
+    fn check_backend<B>()
+    where
+        B: Backend<Item> + Send + Sync,
+    {
+        if let Err(failure) = check::<B>(seed_from_env(), 100) {
+            panic!("{}", failure);
+        }
+    }
+
+    #[test]
+    fn test_chase_lev_is_linearizable() {
+        check_backend::<ChaseLev<Item>>();
+    }
+
+    #[test]
+    fn test_mutex_buffer_is_linearizable() {
+        check_backend::<MutexBuffer<Item>>();
+    }
+
+    /// Pops from the wrong end, so the owner sees its tasks in FIFO order.
+    struct FifoPop(MutexBuffer<Item>);
+
+    impl Backend<Item> for FifoPop {
+        fn with_capacity(capacity: usize) -> Self {
+            Self(MutexBuffer::with_capacity(capacity))
+        }
+
+        unsafe fn push(&self, task: Box<Item>) {
+            self.0.push(task)
+        }
+
+        unsafe fn pop(&self) -> Steal<Box<Item>> {
+            self.0.steal()
+        }
+
+        fn steal(&self) -> Steal<Box<Item>> {
+            self.0.steal()
+        }
+
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    #[test]
+    fn test_finds_and_shrinks_violations() {
+        let failure = check::<FifoPop>(1, 100).expect_err("bug went unnoticed");
+        let kinds: Vec<_> = failure.workload.threads[0]
+            .iter()
+            .map(|op| matches!(op, Op::Push(_)))
+            .collect();
+
+        // Two pushes and a pop are the least that tells the ends apart.
+        assert_eq!(failure.workload.threads.len(), 1);
+        assert_eq!(kinds, [true, true, false]);
+        assert!(failure.to_string().contains("MEMO_LIN_SEED=1"));
+    }
+}
@@ -0,0 +1,312 @@
+use std::{
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use super::schedule::Steal;
+
+/// Live counters of one worker. Only the worker itself writes them, so
+/// relaxed atomics are enough; readers get a slightly stale but consistent
+/// enough picture.
+#[derive(Debug, Default)]
+pub(crate) struct WorkerCounters {
+    pushes: AtomicU64,
+    pops: AtomicU64,
+    steals: AtomicU64,
+    failed_steals: AtomicU64,
+    steal_retries: AtomicU64,
+    parks: AtomicU64,
+    unparks: AtomicU64,
+    panics: AtomicU64,
+    max_queue_depth: AtomicU64,
+    busy_nanos: AtomicU64,
+    idle_nanos: AtomicU64,
+}
+
+fn bump(counter: &AtomicU64, by: u64) {
+    counter.fetch_add(by, Ordering::Relaxed);
+}
+
+impl WorkerCounters {
+    pub(crate) fn push(&self, depth: usize) {
+        bump(&self.pushes, 1);
+        self.queue_depth(depth);
+    }
+
+    pub(crate) fn pop(&self) {
+        bump(&self.pops, 1);
+    }
+
+    pub(crate) fn queue_depth(&self, depth: usize) {
+        self.max_queue_depth
+            .fetch_max(depth as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn steal<T>(&self, steal: &Steal<T>) {
+        match steal {
+            Steal::Success(_) => bump(&self.steals, 1),
+            Steal::Retry => bump(&self.steal_retries, 1),
+            Steal::Empty => bump(&self.failed_steals, 1),
+        }
+    }
+
+    pub(crate) fn park(&self, woken: bool) {
+        bump(&self.parks, 1);
+        if woken {
+            bump(&self.unparks, 1);
+        }
+    }
+
+    pub(crate) fn panic(&self) {
+        bump(&self.panics, 1);
+    }
+
+    pub(crate) fn busy(&self, time: Duration) {
+        bump(&self.busy_nanos, time.as_nanos() as u64);
+    }
+
+    pub(crate) fn idle(&self, time: Duration) {
+        bump(&self.idle_nanos, time.as_nanos() as u64);
+    }
+
+    pub(crate) fn snapshot(&self) -> WorkerMetrics {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        WorkerMetrics {
+            pushes: load(&self.pushes),
+            pops: load(&self.pops),
+            steals: load(&self.steals),
+            failed_steals: load(&self.failed_steals),
+            steal_retries: load(&self.steal_retries),
+            parks: load(&self.parks),
+            unparks: load(&self.unparks),
+            panics: load(&self.panics),
+            max_queue_depth: load(&self.max_queue_depth),
+            busy_time: Duration::from_nanos(load(&self.busy_nanos)),
+            idle_time: Duration::from_nanos(load(&self.idle_nanos)),
+        }
+    }
+}
+
+/// What one worker has been doing since the scheduler started.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerMetrics {
+    /// Tasks the worker pushed onto its own deques.
+    pub pushes: u64,
+    /// Tasks it popped back off them.
+    pub pops: u64,
+    /// Steal attempts on a peer that got at least one task.
+    pub steals: u64,
+    /// Steal attempts that found the peer empty.
+    pub failed_steals: u64,
+    /// Steal attempts that lost a race and had to be retried.
+    pub steal_retries: u64,
+    /// Times the worker went to sleep, counted once it wakes up again.
+    pub parks: u64,
+    /// Times it was woken up by new work rather than a timeout.
+    pub unparks: u64,
+    /// Tasks that panicked on the worker, not counting those whose join
+    /// handle took the panic.
+    pub panics: u64,
+    /// Most tasks its deques held at once.
+    pub max_queue_depth: u64,
+    /// Time spent running tasks.
+    pub busy_time: Duration,
+    /// Time spent looking for work or asleep.
+    pub idle_time: Duration,
+}
+
+impl WorkerMetrics {
+    /// Adds `other` to these counters; the high-water mark takes the max.
+    fn merge(mut self, other: &WorkerMetrics) -> Self {
+        self.pushes += other.pushes;
+        self.pops += other.pops;
+        self.steals += other.steals;
+        self.failed_steals += other.failed_steals;
+        self.steal_retries += other.steal_retries;
+        self.parks += other.parks;
+        self.unparks += other.unparks;
+        self.panics += other.panics;
+        self.max_queue_depth = self.max_queue_depth.max(other.max_queue_depth);
+        self.busy_time += other.busy_time;
+        self.idle_time += other.idle_time;
+        self
+    }
+}
+
+/// Snapshot of a scheduler's counters, from `Scheduler::metrics`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Metrics {
+    /// One entry per worker, by index.
+    pub workers: Vec<WorkerMetrics>,
+    /// Tasks waiting in the injector queues.
+    pub injector_depth: usize,
+    /// Tasks dropped under `Backpressure::Reject`.
+    pub rejected: u64,
+}
+
+type Metric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&WorkerMetrics) -> f64,
+);
+
+/// Name, type, help text and value of every per-worker metric.
+#[rustfmt::skip]
+const WORKER_METRICS: [Metric; 11] = [
+    ("memo_worker_pushes_total", "counter", "Tasks pushed onto the worker's own deques.", |m| m.pushes as f64),
+    ("memo_worker_pops_total", "counter", "Tasks popped off the worker's own deques.", |m| m.pops as f64),
+    ("memo_worker_steals_total", "counter", "Steal attempts that got at least one task.", |m| m.steals as f64),
+    ("memo_worker_failed_steals_total", "counter", "Steal attempts that found the victim empty.", |m| m.failed_steals as f64),
+    ("memo_worker_steal_retries_total", "counter", "Steal attempts that lost a race.", |m| m.steal_retries as f64),
+    ("memo_worker_parks_total", "counter", "Times the worker went to sleep.", |m| m.parks as f64),
+    ("memo_worker_unparks_total", "counter", "Times the worker was woken by new work.", |m| m.unparks as f64),
+    ("memo_worker_panics_total", "counter", "Tasks that panicked on the worker.", |m| m.panics as f64),
+    ("memo_worker_queue_depth_max", "gauge", "Most tasks the worker's deques held at once.", |m| m.max_queue_depth as f64),
+    ("memo_worker_busy_seconds_total", "counter", "Time spent running tasks.", |m| m.busy_time.as_secs_f64()),
+    ("memo_worker_idle_seconds_total", "counter", "Time spent looking for work or asleep.", |m| m.idle_time.as_secs_f64()),
+];
+
+impl Metrics {
+    /// Counters of all workers added up.
+    pub fn total(&self) -> WorkerMetrics {
+        self.workers
+            .iter()
+            .fold(WorkerMetrics::default(), WorkerMetrics::merge)
+    }
+
+    /// Writes the snapshot in the Prometheus text exposition format, with
+    /// a `worker` label on the per-worker metrics.
+    pub fn write_prometheus<W>(&self, out: &mut W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        for (name, kind, help, value) in WORKER_METRICS {
+            writeln!(out, "# HELP {} {}", name, help)?;
+            writeln!(out, "# TYPE {} {}", name, kind)?;
+
+            for (index, worker) in self.workers.iter().enumerate() {
+                writeln!(out, "{}{{worker=\"{}\"}} {}", name, index, value(worker))?;
+            }
+        }
+
+        writeln!(
+            out,
+            "# HELP memo_injector_depth Tasks waiting in the injector queues."
+        )?;
+        writeln!(out, "# TYPE memo_injector_depth gauge")?;
+        writeln!(out, "memo_injector_depth {}", self.injector_depth)?;
+
+        writeln!(
+            out,
+            "# HELP memo_rejected_tasks_total Tasks dropped because their queue was full."
+        )?;
+        writeln!(out, "# TYPE memo_rejected_tasks_total counter")?;
+        writeln!(out, "memo_rejected_tasks_total {}", self.rejected)
+    }
+}
+
+#[cfg(test)]
+mod metrics_test {
+    use super::*;
+
+    #[test]
+    fn test_counters_snapshot() {
+        let counters = WorkerCounters::default();
+
+        counters.push(3);
+        counters.push(1);
+        counters.pop();
+        counters.steal(&Steal::Success(()));
+        counters.steal(&Steal::<()>::Retry);
+        counters.steal(&Steal::<()>::Empty);
+        counters.steal(&Steal::<()>::Empty);
+        counters.park(true);
+        counters.park(false);
+        counters.panic();
+        counters.busy(Duration::from_millis(3));
+        counters.idle(Duration::from_millis(4));
+
+        assert_eq!(
+            counters.snapshot(),
+            WorkerMetrics {
+                pushes: 2,
+                pops: 1,
+                steals: 1,
+                failed_steals: 2,
+                steal_retries: 1,
+                parks: 2,
+                unparks: 1,
+                panics: 1,
+                max_queue_depth: 3,
+                busy_time: Duration::from_millis(3),
+                idle_time: Duration::from_millis(4),
+            }
+        );
+    }
+
+    #[test]
+    fn test_total() {
+        let metrics = Metrics {
+            workers: vec![
+                WorkerMetrics {
+                    pushes: 2,
+                    max_queue_depth: 7,
+                    busy_time: Duration::from_secs(1),
+                    ..Default::default()
+                },
+                WorkerMetrics {
+                    pushes: 3,
+                    max_queue_depth: 5,
+                    busy_time: Duration::from_secs(2),
+                    ..Default::default()
+                },
+            ],
+            injector_depth: 0,
+            rejected: 0,
+        };
+
+        let total = metrics.total();
+        assert_eq!(total.pushes, 5);
+        assert_eq!(total.max_queue_depth, 7);
+        assert_eq!(total.busy_time, Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_write_prometheus() {
+        let metrics = Metrics {
+            workers: vec![
+                WorkerMetrics {
+                    pushes: 4,
+                    busy_time: Duration::from_millis(1500),
+                    ..Default::default()
+                },
+                WorkerMetrics::default(),
+            ],
+            injector_depth: 2,
+            rejected: 1,
+        };
+
+        let mut out = Vec::new();
+        metrics.write_prometheus(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(
+            &lines[..4],
+            [
+                "# HELP memo_worker_pushes_total Tasks pushed onto the worker's own deques.",
+                "# TYPE memo_worker_pushes_total counter",
+                "memo_worker_pushes_total{worker=\"0\"} 4",
+                "memo_worker_pushes_total{worker=\"1\"} 0",
+            ]
+        );
+        assert!(lines.contains(&"memo_worker_busy_seconds_total{worker=\"0\"} 1.5"));
+        assert!(lines.contains(&"# TYPE memo_worker_queue_depth_max gauge"));
+        assert!(lines.contains(&"memo_injector_depth 2"));
+        assert_eq!(lines.last(), Some(&"memo_rejected_tasks_total 1"));
+        assert_eq!(lines.len(), WORKER_METRICS.len() * 4 + 6);
+    }
+}
@@ -1 +1,24 @@
-mod schedule;
\ No newline at end of file
+pub mod backend;
+pub mod backpressure;
+pub mod cancel;
+pub mod chase_lev;
+pub mod clock;
+pub mod executor;
+pub mod injector;
+pub mod iter;
+mod job;
+pub mod join;
+pub mod join_handle;
+#[cfg(test)]
+mod linearizability;
+pub mod metrics;
+pub mod priority;
+mod rng;
+pub mod schedule;
+pub mod scheduler;
+pub mod scope;
+pub mod simulation;
+mod sleep;
+mod sync;
+pub mod timer;
+pub mod victim;
\ No newline at end of file
@@ -0,0 +1,80 @@
+/// How urgently a task should run.
+///
+/// Workers keep one deque per priority and look for work in the higher
+/// ones first, both in their own deques and when stealing. To keep a steady
+/// stream of urgent tasks from starving the rest, every `AGING_INTERVAL`th
+/// search starts at a lower priority instead, taking turns between `Normal`
+/// and `Background`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Latency-sensitive work, such as request handlers.
+    High,
+    #[default]
+    Normal,
+    /// Batch work that only needs to make progress eventually.
+    Background,
+}
+
+/// How many searches for work pass between two that start at a lower
+/// priority.
+pub const AGING_INTERVAL: usize = 16;
+
+impl Priority {
+    pub const COUNT: usize = 3;
+
+    /// Every priority, highest first.
+    pub const ALL: [Priority; Priority::COUNT] =
+        [Priority::High, Priority::Normal, Priority::Background];
+
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Order in which a worker looks through its queues on its `tick`th
+    /// search for work.
+    pub(crate) fn search_order(tick: usize) -> [Priority; Priority::COUNT] {
+        use Priority::*;
+
+        if !tick.is_multiple_of(AGING_INTERVAL) {
+            return Self::ALL;
+        }
+
+        match (tick / AGING_INTERVAL) % 2 {
+            0 => [Normal, High, Background],
+            _ => [Background, High, Normal],
+        }
+    }
+}
+
+#[cfg(test)]
+mod priority_test {
+    use super::*;
+
+    #[test]
+    fn test_all_is_highest_first() {
+        let mut sorted = Priority::ALL;
+        sorted.sort();
+
+        assert_eq!(sorted, Priority::ALL);
+        assert_eq!(Priority::default(), Priority::Normal);
+        assert!(Priority::ALL.iter().enumerate().all(|(i, p)| p.index() == i));
+    }
+
+    #[test]
+    fn test_search_order_ages_lower_priorities() {
+        let firsts: Vec<_> = (1..=4 * AGING_INTERVAL)
+            .map(|tick| Priority::search_order(tick)[0])
+            .collect();
+
+        let count = |priority| firsts.iter().filter(|&&p| p == priority).count();
+        assert_eq!(count(Priority::Normal), 2);
+        assert_eq!(count(Priority::Background), 2);
+        assert_eq!(count(Priority::High), 4 * AGING_INTERVAL - 4);
+
+        for tick in 0..4 * AGING_INTERVAL {
+            let mut order = Priority::search_order(tick);
+            order.sort();
+            assert_eq!(order, Priority::ALL);
+        }
+    }
+}
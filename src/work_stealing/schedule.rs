@@ -1,121 +1,876 @@
-use std::sync::{atomic::{AtomicUsize, Ordering}, Mutex};
+use std::{cell::Cell, marker::PhantomData, sync::Arc};
 
-pub enum Status {
+use super::{backend::Backend, chase_lev::ChaseLev};
+
+/// Default cap on how many tasks a single batch steal moves.
+pub const MAX_BATCH: usize = 32;
+
+/// Outcome of taking a task out of a deque, by either `pop` or `steal`.
+#[must_use]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Steal<T> {
+    /// The deque was empty.
     Empty,
-    Abort,
+    /// A task was taken.
+    Success(T),
+    /// Lost a race with another thread; the deque may still hold tasks and
+    /// the operation should be retried.
+    Retry,
+}
+
+impl<T> Steal<T> {
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Steal::Empty)
+    }
+
+    pub fn is_success(&self) -> bool {
+        matches!(self, Steal::Success(_))
+    }
+
+    pub fn is_retry(&self) -> bool {
+        matches!(self, Steal::Retry)
+    }
+
+    pub fn success(self) -> Option<T> {
+        match self {
+            Steal::Success(task) => Some(task),
+            _ => None,
+        }
+    }
+
+    /// Returns `self` if it holds a task, otherwise the result of `f`.
+    /// A `Retry` is kept over an `Empty` from `f`, since something may
+    /// still be left to take.
+    pub fn or_else<F>(self, f: F) -> Steal<T>
+    where
+        F: FnOnce() -> Steal<T>,
+    {
+        match self {
+            Steal::Success(_) => self,
+            Steal::Empty => f(),
+            Steal::Retry => match f() {
+                Steal::Success(task) => Steal::Success(task),
+                _ => Steal::Retry,
+            },
+        }
+    }
+}
+
+/// Collects the results of trying several victims in turn. Stops at the
+/// first success; otherwise `Retry` if any attempt asked for one, and
+/// `Empty` if every victim was empty.
+impl<T> FromIterator<Steal<T>> for Steal<T> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = Steal<T>>,
+    {
+        let mut result = Steal::Empty;
+
+        for steal in iter {
+            match steal {
+                Steal::Success(task) => return Steal::Success(task),
+                Steal::Retry => result = Steal::Retry,
+                Steal::Empty => {}
+            }
+        }
+
+        result
+    }
 }
 
+/// A unit of work. Running a task consumes it, so it can move whatever it
+/// owns out of itself.
 pub trait Task {
-    fn execute(&self);
+    fn execute(self: Box<Self>);
+
+    /// Whether the task was withdrawn, see `CancellationToken`. Deques and
+    /// injectors skip cancelled tasks as they take them out, calling
+    /// `cancel` on them instead of handing them over.
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+
+    /// Disposes of a cancelled task that never ran. Just drops it by
+    /// default.
+    fn cancel(self: Box<Self>) {}
 }
 
-struct Tasks;
+/// Passes `steal` through, unless it took a cancelled task. That one is
+/// disposed of and `None` returned, so the caller takes the next task.
+pub(crate) fn skip_cancelled<T>(steal: Steal<Box<T>>) -> Option<Steal<Box<T>>>
+where
+    T: Task + ?Sized,
+{
+    match steal {
+        Steal::Success(task) => unless_cancelled(task).map(Steal::Success),
+        steal => Some(steal),
+    }
+}
 
-impl Task for Tasks {
-    fn execute(&self) {
-        println!("execute");
+/// Hands `task` back, unless it is cancelled, in which case it is disposed
+/// of. Must not be called under a queue lock, since `cancel` may queue
+/// other tasks.
+pub(crate) fn unless_cancelled<T>(task: Box<T>) -> Option<Box<T>>
+where
+    T: Task + ?Sized,
+{
+    if task.is_cancelled() {
+        task.cancel();
+        return None;
     }
+
+    Some(task)
 }
 
-type Buffer<T> = Vec<Option<Box<T>>>;
+/// Any one-shot closure is a task.
+impl<F> Task for F
+where
+    F: FnOnce(),
+{
+    fn execute(self: Box<Self>) {
+        (*self)()
+    }
+}
 
 #[derive(Debug)]
-pub struct WorkStealingDeque<T>
+pub struct WorkStealingDeque<T, B = ChaseLev<T>>
 where
-    T: Task,
+    T: Task + ?Sized,
+    B: Backend<T>,
 {
-    buffer: Mutex<Buffer<T>>,
+    buffer: B,
+    /// Most tasks the deque may hold, if it is bounded.
+    bound: Option<usize>,
+    _marker: PhantomData<fn(Box<T>) -> Box<T>>,
 }
 
-impl<T> WorkStealingDeque<T>
+impl<T, B> WorkStealingDeque<T, B>
 where
-    T: Task,
+    T: Task + ?Sized,
+    B: Backend<T>,
 {
-    pub fn new(capacity: usize) -> Self {
-        Self {
-            buffer: Mutex::new(Buffer::with_capacity(capacity)),
-        }
+    /// Creates a deque and returns its owner handle together with a
+    /// stealer that can be cloned and shared with other threads.
+    ///
+    /// `capacity` only sets how much room is allocated up front; the deque
+    /// grows as needed. See `bounded` for one that does not.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(capacity: usize) -> (Worker<T, B>, Stealer<T, B>) {
+        Self::create(capacity, None)
+    }
+
+    /// Creates a deque that never holds more than `capacity` tasks. Once it
+    /// is full, `Worker::try_push` hands tasks back and batch steals into
+    /// it only move as many tasks as fit.
+    pub fn bounded(capacity: usize) -> (Worker<T, B>, Stealer<T, B>) {
+        assert!(capacity > 0, "a bounded deque needs room for a task");
+
+        Self::create(capacity, Some(capacity))
     }
 
-    pub fn push(&mut self, task: Box<T>) {
-        let mut buffer = self.buffer.lock().unwrap();
+    fn create(capacity: usize, bound: Option<usize>) -> (Worker<T, B>, Stealer<T, B>) {
+        let deque = Arc::new(Self {
+            buffer: B::with_capacity(capacity),
+            bound,
+            _marker: PhantomData,
+        });
+
+        let worker = Worker {
+            deque: deque.clone(),
+            _not_sync: PhantomData,
+        };
 
-        buffer.push(Some(task));
+        (worker, Stealer { deque })
     }
+}
 
-    pub fn pop(&mut self) -> Result<Option<Box<T>>, Status> {
-        let mut buffer = self.buffer.lock().unwrap();
+/// Owner side of a `WorkStealingDeque`.
+///
+/// Only the owner pushes and pops, so the handle can be moved to another
+/// thread but not shared between threads.
+#[derive(Debug)]
+pub struct Worker<T, B = ChaseLev<T>>
+where
+    T: Task + ?Sized,
+    B: Backend<T>,
+{
+    deque: Arc<WorkStealingDeque<T, B>>,
+    _not_sync: PhantomData<Cell<()>>,
+}
 
-        if buffer.is_empty() {
-            return Err(Status::Empty);
+impl<T, B> Worker<T, B>
+where
+    T: Task + ?Sized,
+    B: Backend<T>,
+{
+    /// # Panics
+    ///
+    /// Panics if the deque is bounded and full; use `try_push` there.
+    pub fn push(&self, task: Box<T>) {
+        if self.try_push(task).is_err() {
+            panic!("pushed onto a full bounded deque");
+        }
+    }
+
+    /// Pushes `task`, or hands it back if the deque is bounded and full.
+    pub fn try_push(&self, task: Box<T>) -> Result<(), Box<T>> {
+        if self.is_full() {
+            return Err(task);
         }
 
-        while let Some(slot) = buffer.pop() {
-            if slot.is_some() {
-                return Ok(slot);
+        unsafe { self.deque.buffer.push(task) };
+        Ok(())
+    }
+
+    /// Pops the most recently pushed task, so the owner works through its
+    /// deque in LIFO order.
+    pub fn pop(&self) -> Steal<Box<T>> {
+        loop {
+            if let Some(steal) = skip_cancelled(unsafe { self.deque.buffer.pop() }) {
+                return steal;
             }
         }
+    }
+
+    pub fn stealer(&self) -> Stealer<T, B> {
+        Stealer {
+            deque: self.deque.clone(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.deque.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deque.buffer.is_empty()
+    }
+
+    /// Most tasks the deque may hold, or `None` if it is unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.deque.bound
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.room() == 0
+    }
 
-        Err(Status::Abort)
+    /// Free slots left, or `usize::MAX` for an unbounded deque. Thieves
+    /// only ever take tasks out, so the owner can trust the answer until
+    /// it pushes again.
+    pub(crate) fn room(&self) -> usize {
+        match self.deque.bound {
+            Some(bound) => bound.saturating_sub(self.len()),
+            None => usize::MAX,
+        }
     }
+}
+
+/// Thief side of a `WorkStealingDeque`, shareable between any number of
+/// threads.
+#[derive(Debug)]
+pub struct Stealer<T, B = ChaseLev<T>>
+where
+    T: Task + ?Sized,
+    B: Backend<T>,
+{
+    deque: Arc<WorkStealingDeque<T, B>>,
+}
 
+impl<T, B> Stealer<T, B>
+where
+    T: Task + ?Sized,
+    B: Backend<T>,
+{
     /// If the deque is empty, returns Empty. Otherwise,
     /// returns the element successfully stolen from the top of
-    /// the deque, or returns Abort if this process loses a race
+    /// the deque, or returns Retry if this process loses a race
     /// with another process to steal the topmost element
-    pub fn steal(&mut self) -> Option<Box<T>> {
-        let mut buffer = self.buffer.lock().unwrap();
+    ///
+    /// The top holds the oldest task, so thieves take tasks in the order
+    /// they were pushed, from the opposite end to `Worker::pop`.
+    pub fn steal(&self) -> Steal<Box<T>> {
+        loop {
+            if let Some(steal) = skip_cancelled(self.deque.buffer.steal()) {
+                return steal;
+            }
+        }
+    }
+
+    /// Moves up to half of the tasks, at most `MAX_BATCH`, into `dest`.
+    pub fn steal_batch(&self, dest: &Worker<T, B>) -> Steal<()> {
+        self.steal_batch_with_limit(dest, MAX_BATCH)
+    }
+
+    /// Moves up to half of the tasks, at most `limit`, into `dest`. Tasks
+    /// keep their order, so the oldest stolen task is also the first one
+    /// other thieves would take from `dest`.
+    ///
+    /// A bounded `dest` only takes as many tasks as it has room for; if it
+    /// is full, nothing is moved and the result is `Empty`.
+    pub fn steal_batch_with_limit(&self, dest: &Worker<T, B>, limit: usize) -> Steal<()> {
+        assert!(limit > 0, "batch limit must be positive");
 
-        if buffer.is_empty() {
-            return None;
+        if Arc::ptr_eq(&self.deque, &dest.deque) {
+            return if dest.is_empty() {
+                Steal::Empty
+            } else {
+                Steal::Success(())
+            };
         }
 
-        for slot in buffer.iter_mut().rev() {
-            if slot.is_some() {
-                return slot.take();
-            }
+        let limit = limit.min(dest.room());
+        if limit == 0 {
+            return Steal::Empty;
+        }
+
+        unsafe { self.deque.buffer.steal_batch(&dest.deque.buffer, limit) }
+    }
+
+    /// Like `steal_batch`, but returns the oldest stolen task directly
+    /// instead of pushing it into `dest`.
+    pub fn steal_batch_and_pop(&self, dest: &Worker<T, B>) -> Steal<Box<T>> {
+        self.steal_batch_with_limit_and_pop(dest, MAX_BATCH)
+    }
+
+    pub fn steal_batch_with_limit_and_pop(
+        &self,
+        dest: &Worker<T, B>,
+        limit: usize,
+    ) -> Steal<Box<T>> {
+        assert!(limit > 0, "batch limit must be positive");
+
+        if Arc::ptr_eq(&self.deque, &dest.deque) {
+            return dest.pop();
         }
 
-        None
+        // The popped task never enters `dest`, so it needs no room there.
+        let limit = limit.min(dest.room().saturating_add(1));
+        unsafe { self.deque.buffer.steal_batch_and_pop(&dest.deque.buffer, limit) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.deque.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deque.buffer.is_empty()
+    }
+}
+
+impl<T, B> Clone for Stealer<T, B>
+where
+    T: Task + ?Sized,
+    B: Backend<T>,
+{
+    fn clone(&self) -> Self {
+        Self {
+            deque: self.deque.clone(),
+        }
     }
 }
 
 #[cfg(test)]
 mod work_steal_schedule_test {
     use super::*;
+    use crate::work_stealing::backend::MutexBuffer;
+    use std::thread;
 
     struct TestTask(pub u32);
 
     impl Task for TestTask {
-        fn execute(&self) {
-            println!("execute {}", self.0);
-        }
+        fn execute(self: Box<Self>) {}
     }
 
     #[test]
     fn test_push_pop() {
-        let mut deque: WorkStealingDeque<TestTask> = WorkStealingDeque::new(10);
+        let (worker, _) = WorkStealingDeque::<TestTask>::new(10);
 
-        deque.push(Box::new(TestTask(1)));
-        assert!(deque.pop().is_ok());
+        worker.push(Box::new(TestTask(1)));
+        assert!(worker.pop().is_success());
 
-        deque.push(Box::new(TestTask(2)));
-        assert!(deque.pop().is_ok());
+        worker.push(Box::new(TestTask(2)));
+        assert!(worker.pop().is_success());
 
-        assert!(deque.pop().is_err());
+        assert!(worker.pop().is_empty());
     }
     
     #[test]
     fn test_steal() {
-        let mut deque: WorkStealingDeque<TestTask> = WorkStealingDeque::new(10);
+        let (worker, stealer) = WorkStealingDeque::<TestTask, MutexBuffer<TestTask>>::new(10);
+
+        worker.push(Box::new(TestTask(1)));
+        worker.push(Box::new(TestTask(2)));
+        worker.push(Box::new(TestTask(3)));
+
+        assert_eq!(stealer.steal().success().map(|task| task.0), Some(1));
+        assert_eq!(stealer.steal().success().map(|task| task.0), Some(2));
+        assert_eq!(stealer.steal().success().map(|task| task.0), Some(3));
+        assert!(stealer.steal().is_empty());
+    }
+
+    #[test]
+    fn test_lock_free_steal() {
+        let (worker, stealer) = WorkStealingDeque::<TestTask>::new(10);
+
+        worker.push(Box::new(TestTask(1)));
+        worker.push(Box::new(TestTask(2)));
+        worker.push(Box::new(TestTask(3)));
+
+        assert_eq!(stealer.steal().success().map(|task| task.0), Some(1));
+        assert_eq!(worker.pop().success().map(|task| task.0), Some(3));
+        assert_eq!(stealer.steal().success().map(|task| task.0), Some(2));
+        assert!(stealer.steal().is_empty());
+        assert!(worker.is_empty());
+    }
+
+    fn check_pop_and_steal_order<B: Backend<TestTask>>() {
+        let (worker, stealer) = WorkStealingDeque::<TestTask, B>::new(10);
+
+        for i in 1..=6 {
+            worker.push(Box::new(TestTask(i)));
+        }
+
+        assert_eq!(worker.pop().success().map(|task| task.0), Some(6));
+        assert_eq!(stealer.steal().success().map(|task| task.0), Some(1));
+        assert_eq!(worker.pop().success().map(|task| task.0), Some(5));
+        assert_eq!(stealer.steal().success().map(|task| task.0), Some(2));
+
+        worker.push(Box::new(TestTask(7)));
+
+        assert_eq!(stealer.steal().success().map(|task| task.0), Some(3));
+        assert_eq!(worker.pop().success().map(|task| task.0), Some(7));
+        assert_eq!(worker.pop().success().map(|task| task.0), Some(4));
+        assert!(stealer.steal().is_empty());
+        assert!(worker.pop().is_empty());
+    }
+
+    #[test]
+    fn test_mixed_pop_and_steal_order() {
+        check_pop_and_steal_order::<ChaseLev<TestTask>>();
+        check_pop_and_steal_order::<MutexBuffer<TestTask>>();
+    }
+
+    #[test]
+    fn test_steal_combinators() {
+        let empty: Steal<u32> = Steal::Empty;
+        let retry: Steal<u32> = Steal::Retry;
+
+        assert!(empty.is_empty() && retry.is_retry());
+        assert!(Steal::Success(1).is_success());
+        assert_eq!(Steal::Success(1).success(), Some(1));
+        assert_eq!(retry.success(), None);
+
+        assert_eq!(empty.or_else(|| Steal::Success(2)), Steal::Success(2));
+        assert_eq!(Steal::Success(1).or_else(|| Steal::Success(2)), Steal::Success(1));
+        assert_eq!(retry.or_else(|| Steal::Empty), Steal::Retry);
+        assert_eq!(retry.or_else(|| Steal::Success(2)), Steal::Success(2));
+        assert_eq!(empty.or_else(|| Steal::Retry), Steal::Retry);
+    }
+
+    #[test]
+    fn test_collect_steals() {
+        let all_empty: Steal<u32> = vec![Steal::Empty, Steal::Empty].into_iter().collect();
+        assert_eq!(all_empty, Steal::Empty);
 
-        deque.push(Box::new(TestTask(1)));
-        deque.push(Box::new(TestTask(2)));
-        deque.push(Box::new(TestTask(3)));
+        let retry: Steal<u32> = vec![Steal::Retry, Steal::Empty].into_iter().collect();
+        assert_eq!(retry, Steal::Retry);
 
-        assert_eq!(deque.steal().map(|task| task.0), Some(3));
-        assert_eq!(deque.steal().map(|task| task.0), Some(2));
-        assert_eq!(deque.steal().map(|task| task.0), Some(1));
-        assert!(deque.steal().is_none());
+        let mut tried = 0;
+        let first: Steal<u32> = [Steal::Retry, Steal::Success(1), Steal::Success(2)]
+            .into_iter()
+            .inspect(|_| tried += 1)
+            .collect();
+        assert_eq!(first, Steal::Success(1));
+        assert_eq!(tried, 2);
     }
-}
\ No newline at end of file
+
+    fn check_steal_batch<B: Backend<TestTask>>() {
+        let (victim, stealer) = WorkStealingDeque::<TestTask, B>::new(10);
+        let (thief, _) = WorkStealingDeque::<TestTask, B>::new(10);
+
+        for i in 0..10 {
+            victim.push(Box::new(TestTask(i)));
+        }
+
+        assert!(stealer.steal_batch(&thief).is_success());
+        assert_eq!(thief.len(), 5);
+        assert_eq!(victim.len(), 5);
+        assert_eq!(thief.pop().success().map(|task| task.0), Some(4));
+        assert_eq!(victim.pop().success().map(|task| task.0), Some(9));
+
+        let first = stealer.steal_batch_with_limit_and_pop(&thief, 1);
+        assert_eq!(first.success().map(|task| task.0), Some(5));
+        assert_eq!(thief.len(), 4);
+
+        let first = stealer.steal_batch_and_pop(&thief);
+        assert_eq!(first.success().map(|task| task.0), Some(6));
+        assert_eq!(thief.len(), 5);
+        assert_eq!(victim.len(), 1);
+
+        assert!(stealer.steal_batch(&thief).is_success());
+        assert!(stealer.steal_batch(&thief).is_empty());
+        assert!(stealer.steal_batch_and_pop(&thief).is_empty());
+        assert_eq!(thief.len(), 6);
+    }
+
+    fn check_steal_batch_limit<B: Backend<TestTask>>() {
+        let (victim, stealer) = WorkStealingDeque::<TestTask, B>::new(10);
+        let (thief, _) = WorkStealingDeque::<TestTask, B>::new(10);
+
+        for i in 0..1000 {
+            victim.push(Box::new(TestTask(i)));
+        }
+
+        assert!(stealer.steal_batch(&thief).is_success());
+        assert_eq!(thief.len(), MAX_BATCH);
+
+        assert!(stealer.steal_batch_with_limit(&thief, 100).is_success());
+        assert_eq!(thief.len(), MAX_BATCH + 100);
+        assert_eq!(victim.len(), 1000 - MAX_BATCH - 100);
+    }
+
+    #[test]
+    fn test_steal_batch() {
+        check_steal_batch::<ChaseLev<TestTask>>();
+        check_steal_batch::<MutexBuffer<TestTask>>();
+        check_steal_batch_limit::<ChaseLev<TestTask>>();
+        check_steal_batch_limit::<MutexBuffer<TestTask>>();
+    }
+
+    #[test]
+    fn test_steal_batch_into_own_deque() {
+        let (worker, stealer) = WorkStealingDeque::<TestTask>::new(10);
+
+        assert!(stealer.steal_batch(&worker).is_empty());
+        worker.push(Box::new(TestTask(1)));
+        assert!(stealer.steal_batch(&worker).is_success());
+        assert_eq!(stealer.steal_batch_and_pop(&worker).success().map(|task| task.0), Some(1));
+    }
+
+    fn check_bounded<B: Backend<TestTask>>() {
+        let (worker, stealer) = WorkStealingDeque::<TestTask, B>::bounded(2);
+        let (thief, _) = WorkStealingDeque::<TestTask, B>::bounded(2);
+
+        assert_eq!(worker.capacity(), Some(2));
+        assert!(worker.try_push(Box::new(TestTask(1))).is_ok());
+        assert!(worker.try_push(Box::new(TestTask(2))).is_ok());
+        assert!(worker.is_full());
+
+        let rejected = worker.try_push(Box::new(TestTask(3)));
+        assert_eq!(rejected.err().map(|task| task.0), Some(3));
+        assert_eq!(worker.len(), 2);
+
+        assert_eq!(stealer.steal().success().map(|task| task.0), Some(1));
+        assert!(worker.try_push(Box::new(TestTask(4))).is_ok());
+
+        thief.push(Box::new(TestTask(5)));
+        thief.push(Box::new(TestTask(6)));
+        assert!(stealer.steal_batch(&thief).is_empty());
+        assert_eq!(worker.len(), 2);
+
+        // Popping the stolen task leaves the full thief as it was.
+        let first = stealer.steal_batch_and_pop(&thief);
+        assert_eq!(first.success().map(|task| task.0), Some(2));
+        assert_eq!(thief.len(), 2);
+        assert_eq!(worker.len(), 1);
+    }
+
+    #[test]
+    fn test_bounded() {
+        check_bounded::<ChaseLev<TestTask>>();
+        check_bounded::<MutexBuffer<TestTask>>();
+
+        let (worker, _) = WorkStealingDeque::<TestTask>::new(1);
+        worker.push(Box::new(TestTask(1)));
+        worker.push(Box::new(TestTask(2)));
+        assert_eq!(worker.capacity(), None);
+        assert!(!worker.is_full());
+    }
+
+    #[test]
+    #[should_panic(expected = "full bounded deque")]
+    fn test_push_onto_full_bounded_deque() {
+        let (worker, _) = WorkStealingDeque::<TestTask>::bounded(1);
+
+        worker.push(Box::new(TestTask(1)));
+        worker.push(Box::new(TestTask(2)));
+    }
+
+    /// Task that is cancelled from the start if its flag says so.
+    struct MaybeCancelled(u32, bool);
+
+    impl Task for MaybeCancelled {
+        fn execute(self: Box<Self>) {}
+
+        fn is_cancelled(&self) -> bool {
+            self.1
+        }
+    }
+
+    fn check_skips_cancelled<B: Backend<MaybeCancelled>>() {
+        let (worker, stealer) = WorkStealingDeque::<MaybeCancelled, B>::new(10);
+        let (dest, _) = WorkStealingDeque::<MaybeCancelled, B>::new(10);
+
+        for i in 1..=6 {
+            worker.push(Box::new(MaybeCancelled(i, i % 2 == 0)));
+        }
+
+        assert_eq!(worker.pop().success().map(|task| task.0), Some(5));
+        assert_eq!(stealer.steal().success().map(|task| task.0), Some(1));
+        assert_eq!(
+            stealer.steal_batch_and_pop(&dest).success().map(|task| task.0),
+            Some(3)
+        );
+        assert!(dest.pop().is_empty());
+        assert!(stealer.steal().is_empty());
+        assert!(worker.pop().is_empty());
+    }
+
+    #[test]
+    fn test_skips_cancelled_tasks() {
+        check_skips_cancelled::<ChaseLev<MaybeCancelled>>();
+        check_skips_cancelled::<MutexBuffer<MaybeCancelled>>();
+    }
+
+    fn check_batch_skips_cancelled<B: Backend<MaybeCancelled>>() {
+        let (worker, stealer) = WorkStealingDeque::<MaybeCancelled, B>::new(10);
+        let (dest, _) = WorkStealingDeque::<MaybeCancelled, B>::new(10);
+
+        dest.push(Box::new(MaybeCancelled(10, false)));
+        dest.push(Box::new(MaybeCancelled(11, false)));
+        for i in 1..=6 {
+            worker.push(Box::new(MaybeCancelled(i, i == 1)));
+        }
+
+        // The next task of the batch stands in for the cancelled one, not
+        // the newest task in `dest`.
+        assert_eq!(
+            stealer.steal_batch_and_pop(&dest).success().map(|task| task.0),
+            Some(2)
+        );
+        let rest: Vec<_> = std::iter::from_fn(|| dest.pop().success())
+            .map(|task| task.0)
+            .collect();
+        assert_eq!(rest, [3, 11, 10]);
+    }
+
+    #[test]
+    fn test_batch_skips_cancelled_tasks() {
+        check_batch_skips_cancelled::<ChaseLev<MaybeCancelled>>();
+        check_batch_skips_cancelled::<MutexBuffer<MaybeCancelled>>();
+    }
+
+    fn check_concurrent_steal_batch<B>()
+    where
+        B: Backend<TestTask> + Send + Sync + 'static,
+    {
+        let (victim, stealer) = WorkStealingDeque::<TestTask, B>::new(10);
+
+        for i in 0..10_000 {
+            victim.push(Box::new(TestTask(i)));
+        }
+
+        let thieves: Vec<_> = (0..3)
+            .map(|_| {
+                let stealer = stealer.clone();
+                thread::spawn(move || {
+                    let (thief, _) = WorkStealingDeque::<TestTask, B>::new(10);
+                    let mut seen = Vec::new();
+
+                    loop {
+                        match stealer.steal_batch_with_limit_and_pop(&thief, 7) {
+                            Steal::Success(task) => seen.push(task.0),
+                            Steal::Retry => continue,
+                            Steal::Empty => break,
+                        }
+                        while let Steal::Success(task) = thief.pop() {
+                            seen.push(task.0);
+                        }
+                    }
+                    seen
+                })
+            })
+            .collect();
+
+        let mut seen = Vec::new();
+        while let Steal::Success(task) = victim.pop() {
+            seen.push(task.0);
+        }
+        for thief in thieves {
+            seen.extend(thief.join().unwrap());
+        }
+
+        seen.sort_unstable();
+        assert_eq!(seen, (0..10_000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_concurrent_steal_batch() {
+        check_concurrent_steal_batch::<ChaseLev<TestTask>>();
+        check_concurrent_steal_batch::<MutexBuffer<TestTask>>();
+    }
+
+    #[test]
+    fn test_closure_tasks() {
+        let (worker, stealer) = WorkStealingDeque::<dyn Task + Send>::new(10);
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        for i in 0..3 {
+            let sender = sender.clone();
+            let buffer = vec![i; 4];
+            worker.push(Box::new(move || sender.send(buffer).unwrap()));
+        }
+
+        stealer.steal().success().unwrap().execute();
+        worker.pop().success().unwrap().execute();
+        worker.pop().success().unwrap().execute();
+
+        let received: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(received, vec![vec![0; 4], vec![2; 4], vec![1; 4]]);
+    }
+
+    #[test]
+    fn test_handles_are_thread_safe() {
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+
+        assert_send::<Worker<TestTask>>();
+        assert_send::<Stealer<TestTask>>();
+        assert_sync::<Stealer<TestTask>>();
+        assert_send::<Worker<TestTask, MutexBuffer<TestTask>>>();
+        assert_sync::<Stealer<TestTask, MutexBuffer<TestTask>>>();
+    }
+
+    #[test]
+    fn test_steal_from_other_threads() {
+        let (worker, stealer) = WorkStealingDeque::<TestTask>::new(10);
+
+        for i in 0..1000 {
+            worker.push(Box::new(TestTask(i)));
+        }
+
+        let thieves: Vec<_> = (0..4)
+            .map(|_| {
+                let stealer = stealer.clone();
+                thread::spawn(move || {
+                    let mut stolen = 0;
+                    while stealer.steal().is_success() {
+                        stolen += 1;
+                    }
+                    stolen
+                })
+            })
+            .collect();
+
+        let mut popped = 0;
+        while worker.pop().is_success() {
+            popped += 1;
+        }
+
+        let stolen: usize = thieves.into_iter().map(|thief| thief.join().unwrap()).sum();
+        assert_eq!(popped + stolen, 1000);
+    }
+}
+
+/// Model-checked with loom, which runs each test once for every way its
+/// threads can interleave. Run with
+/// `RUSTFLAGS="--cfg loom" cargo test --release --lib loom_test`.
+#[cfg(all(test, loom))]
+mod loom_test {
+    use super::*;
+    use crate::work_stealing::backend::MutexBuffer;
+    use loom::thread;
+
+    struct TestTask(pub u32);
+
+    impl Task for TestTask {
+        fn execute(self: Box<Self>) {}
+    }
+
+    /// Steals a single task, retrying lost races.
+    fn steal_one<B: Backend<TestTask>>(stealer: &Stealer<TestTask, B>) -> Option<u32> {
+        loop {
+            match stealer.steal() {
+                Steal::Success(task) => return Some(task.0),
+                Steal::Empty => return None,
+                Steal::Retry => thread::yield_now(),
+            }
+        }
+    }
+
+    /// Explores interleavings with up to three forced preemptions, which
+    /// keeps the three-thread models to a few seconds.
+    fn model<F>(f: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(3);
+        builder.check(f);
+    }
+
+    fn pop_all<B: Backend<TestTask>>(worker: &Worker<TestTask, B>, seen: &mut Vec<u32>) {
+        while let Steal::Success(task) = worker.pop() {
+            seen.push(task.0);
+        }
+    }
+
+    fn check_pop_and_steal_race<B>()
+    where
+        B: Backend<TestTask> + Send + Sync + 'static,
+    {
+        model(|| {
+            let (worker, stealer) = WorkStealingDeque::<TestTask, B>::new(4);
+            worker.push(Box::new(TestTask(0)));
+
+            let thief = thread::spawn(move || steal_one(&stealer));
+
+            let mut seen = Vec::new();
+            pop_all(&worker, &mut seen);
+            seen.extend(thief.join().unwrap());
+
+            assert_eq!(seen, [0]);
+        });
+    }
+
+    #[test]
+    fn test_pop_and_steal_race() {
+        check_pop_and_steal_race::<ChaseLev<TestTask>>();
+        check_pop_and_steal_race::<MutexBuffer<TestTask>>();
+    }
+
+    fn check_owner_against_thieves<B>()
+    where
+        B: Backend<TestTask> + Send + Sync + 'static,
+    {
+        model(|| {
+            let (worker, stealer) = WorkStealingDeque::<TestTask, B>::new(4);
+            worker.push(Box::new(TestTask(0)));
+            worker.push(Box::new(TestTask(1)));
+
+            let thieves: Vec<_> = (0..2)
+                .map(|_| {
+                    let stealer = stealer.clone();
+                    thread::spawn(move || steal_one(&stealer))
+                })
+                .collect();
+
+            worker.push(Box::new(TestTask(2)));
+            let mut seen = Vec::new();
+            pop_all(&worker, &mut seen);
+            for thief in thieves {
+                seen.extend(thief.join().unwrap());
+            }
+
+            seen.sort_unstable();
+            assert_eq!(seen, [0, 1, 2]);
+        });
+    }
+
+    #[test]
+    fn test_owner_against_thieves() {
+        check_owner_against_thieves::<ChaseLev<TestTask>>();
+        check_owner_against_thieves::<MutexBuffer<TestTask>>();
+    }
+}
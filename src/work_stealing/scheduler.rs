@@ -0,0 +1,1939 @@
+use std::{
+    any::Any,
+    array,
+    cell::{Cell, RefCell},
+    future::Future,
+    hint,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, Weak,
+    },
+    task::{Wake, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{
+    backpressure::{Backpressure, Rejected},
+    cancel::CancellationToken,
+    clock::{Clock, SystemClock},
+    injector::Injector,
+    job::{LockLatch, StackJob},
+    join_handle::{JoinHandle, Packet, Spawned},
+    metrics::{Metrics, WorkerCounters},
+    priority::Priority,
+    rng::XorShift,
+    schedule::{Steal, Stealer, Task, WorkStealingDeque, Worker},
+    scope::Scope,
+    simulation::{self, Simulation},
+    sleep::{Backoff, Parked, Sleep},
+    timer::{TimerHandle, Timers},
+    victim::{RandomSelector, StealStats, VictimSelector},
+};
+
+const DEQUE_CAPACITY: usize = 64;
+/// Tasks a worker takes from its LIFO slot in a row before it looks at its
+/// deque and the injector again.
+const LIFO_BUDGET: usize = 3;
+/// How deep a worker that is held back by `Backpressure::Block` nests the
+/// tasks it runs to make room, before it spills into the injector instead.
+const BLOCK_HELP_DEPTH: usize = 16;
+
+pub(crate) type Job = dyn Task + Send;
+
+type PanicHandler = dyn Fn(Box<dyn Any + Send>) + Send + Sync;
+
+/// The deques of one worker, one per priority.
+type Deques = [Worker<Job>; Priority::COUNT];
+
+/// Creates the selectors of one scheduler, given its number of workers.
+type SelectorFactory = dyn Fn(usize) -> Vec<Box<dyn VictimSelector>>;
+
+thread_local! {
+    static CURRENT: Cell<*const WorkerThread> = const { Cell::new(ptr::null()) };
+}
+
+/// State shared between the scheduler, its handles and every worker.
+struct Shared {
+    stealers: Vec<[Stealer<Job>; Priority::COUNT]>,
+    injectors: [Injector<Job>; Priority::COUNT],
+    /// Task each worker woke last, which it runs before anything in its
+    /// deque. Peers only take it when they find nothing else to do, which
+    /// covers a worker that stays busy after the wake.
+    lifo_slots: Vec<Mutex<Option<Box<Job>>>>,
+    timers: Timers,
+    sleep: Sleep,
+    /// Where producers held back by `Backpressure::Block` wait for the
+    /// workers to take tasks out of the injectors.
+    room: Sleep,
+    strategy: &'static str,
+    counters: Vec<WorkerCounters>,
+    panic_handler: Option<Box<PanicHandler>>,
+    /// Bound of every worker deque and, for tasks submitted from outside
+    /// the pool, of every injector.
+    capacity: Option<usize>,
+    backpressure: Backpressure,
+    rejected: AtomicU64,
+    shutdown: AtomicBool,
+}
+
+impl Shared {
+    fn handle_panic(&self, payload: Box<dyn Any + Send>) {
+        if let Some(handler) = &self.panic_handler {
+            // A handler that panics itself must not take the worker
+            // down either; there is nobody left to report it to.
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| handler(payload)));
+        }
+    }
+
+    /// Runs `job` on the calling thread, outside of any worker.
+    fn run_inline(&self, job: Box<Job>) {
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| job.execute())) {
+            self.handle_panic(payload);
+        }
+    }
+
+    /// Runs whatever is left in the injectors on the calling thread. Once
+    /// the workers are gone nobody else would, and whoever waits for those
+    /// tasks would wait forever.
+    fn run_orphans(&self) {
+        for injector in &self.injectors {
+            while let Some(job) = injector.steal().success() {
+                self.run_inline(job);
+            }
+        }
+    }
+}
+
+/// Wakes the workers of a scheduler as long as it is around, for a `Clock`
+/// that jumped ahead. A strong reference held by the clock would keep the
+/// scheduler's state alive for as long as the clock.
+struct ClockWaker(Weak<Shared>);
+
+impl Wake for ClockWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if let Some(shared) = self.0.upgrade() {
+            shared.sleep.notify_all();
+        }
+    }
+}
+
+/// Wakes the workers parked in `WorkerThread::wait_until`, so they look at
+/// what they are waiting for again.
+impl Wake for Shared {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.sleep.notify_all();
+    }
+}
+
+/// Per-thread state of a running worker.
+pub(crate) struct WorkerThread {
+    index: usize,
+    workers: [Worker<Job>; Priority::COUNT],
+    /// Priority of the task currently running on this worker.
+    priority: Cell<Priority>,
+    /// Number of searches for work so far, which drives aging.
+    ticks: Cell<usize>,
+    /// Tasks taken from the LIFO slot in a row, see `LIFO_BUDGET`.
+    lifo_polls: Cell<usize>,
+    /// Tasks run from within `submit_job` that are on the stack right now,
+    /// see `BLOCK_HELP_DEPTH`.
+    block_depth: Cell<usize>,
+    shared: Arc<Shared>,
+    selector: RefCell<Box<dyn VictimSelector>>,
+    /// Scratch space for `VictimSelector::select`.
+    victims: RefCell<Vec<usize>>,
+}
+
+impl WorkerThread {
+    fn new(
+        index: usize,
+        workers: [Worker<Job>; Priority::COUNT],
+        shared: Arc<Shared>,
+        selector: Box<dyn VictimSelector>,
+    ) -> Self {
+        let num_threads = shared.stealers.len();
+
+        Self {
+            index,
+            workers,
+            priority: Cell::new(Priority::Normal),
+            ticks: Cell::new(0),
+            lifo_polls: Cell::new(0),
+            block_depth: Cell::new(0),
+            shared,
+            selector: RefCell::new(selector),
+            victims: RefCell::new(Vec::with_capacity(num_threads)),
+        }
+    }
+
+    pub(crate) fn current() -> Option<&'static WorkerThread> {
+        let current = CURRENT.with(Cell::get);
+
+        // The pointer is only set while `run` or `step` is on the stack of
+        // this thread.
+        unsafe { current.as_ref() }
+    }
+
+    /// Looks for a task and runs it, like one round of `run` but on
+    /// whatever thread calls it. Returns whether there was a task.
+    pub(crate) fn step(&self) -> bool {
+        let outer = CURRENT.with(|current| current.replace(self));
+        let task = self.find_task();
+        let found = task.is_some();
+
+        if let Some((task, priority)) = task {
+            self.execute(task, priority);
+        }
+
+        CURRENT.with(|current| current.set(outer));
+        found
+    }
+
+    fn run(&self) {
+        CURRENT.with(|current| current.set(self));
+        let counters = self.counters();
+        let mut backoff = Backoff::new();
+        let mut idle_since = None;
+
+        loop {
+            let shutdown = self.shared.shutdown.load(Ordering::Acquire);
+
+            match self.find_task() {
+                Some((task, priority)) => {
+                    backoff.reset();
+                    let start = Instant::now();
+                    if let Some(idle_since) = idle_since.take() {
+                        counters.idle(start - idle_since);
+                    }
+
+                    self.execute(task, priority);
+                    counters.busy(start.elapsed());
+                }
+                None if shutdown => break,
+                None if backoff.is_completed() => {
+                    let since = idle_since.get_or_insert_with(Instant::now);
+                    self.park(|| false);
+
+                    // Accounts for the sleep right away, so a worker that
+                    // stays idle still shows up in the metrics.
+                    let now = Instant::now();
+                    counters.idle(now - *since);
+                    *since = now;
+                }
+                None => {
+                    idle_since.get_or_insert_with(Instant::now);
+                    backoff.snooze();
+                }
+            }
+        }
+
+        CURRENT.with(|current| current.set(ptr::null()));
+    }
+
+    pub(crate) fn index(&self) -> usize {
+        self.index
+    }
+
+    pub(crate) fn num_threads(&self) -> usize {
+        self.shared.stealers.len()
+    }
+
+    pub(crate) fn priority(&self) -> Priority {
+        self.priority.get()
+    }
+
+    pub(crate) fn sleep(&self) -> &Sleep {
+        &self.shared.sleep
+    }
+
+    /// Waker for whatever a `wait_until` of this worker waits for.
+    pub(crate) fn waker(&self) -> Waker {
+        Waker::from(self.shared.clone())
+    }
+
+    fn counters(&self) -> &WorkerCounters {
+        &self.shared.counters[self.index]
+    }
+
+    /// Tasks in all of this worker's deques.
+    fn queue_depth(&self) -> usize {
+        self.workers.iter().map(Worker::len).sum()
+    }
+
+    /// Pushes onto this worker's deque, or into the injector if the deque
+    /// is full.
+    pub(crate) fn push(&self, task: Box<Job>, priority: Priority) {
+        if let Err(task) = self.try_push(task, priority) {
+            self.shared.injectors[priority.index()].push(task);
+            self.shared.sleep.notify_one();
+        }
+    }
+
+    fn try_push(&self, task: Box<Job>, priority: Priority) -> Result<(), Box<Job>> {
+        self.workers[priority.index()].try_push(task)?;
+        self.counters().push(self.queue_depth());
+        self.shared.sleep.notify_one();
+
+        Ok(())
+    }
+
+    pub(crate) fn pop(&self, priority: Priority) -> Option<Box<Job>> {
+        let task = self.workers[priority.index()].pop().success()?;
+        self.counters().pop();
+
+        Some(task)
+    }
+
+    /// Runs `task` at `priority`. A panicking task is counted and handed
+    /// to the panic handler, the worker itself carries on.
+    pub(crate) fn execute(&self, task: Box<Job>, priority: Priority) {
+        let outer = self.priority.replace(priority);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| task.execute()));
+        self.priority.set(outer);
+
+        if let Err(payload) = result {
+            self.counters().panic();
+            self.shared.handle_panic(payload);
+        }
+    }
+
+    /// Runs other tasks until `done` holds, and parks while there are
+    /// none. Whatever makes `done` hold must then wake this worker's
+    /// `sleep`, directly or through its `waker`.
+    pub(crate) fn wait_until<F>(&self, done: F)
+    where
+        F: Fn() -> bool,
+    {
+        // Simulated workers share one thread, so the others only get to
+        // run if this one lets them.
+        if simulation::help_until(&done) {
+            return;
+        }
+
+        let mut backoff = Backoff::new();
+
+        while !done() {
+            match self.find_task() {
+                Some((task, priority)) => {
+                    backoff.reset();
+                    self.execute(task, priority);
+                }
+                None if backoff.is_completed() => self.park(&done),
+                None => backoff.snooze(),
+            }
+        }
+    }
+
+    /// Parks until there is work, or `done` holds.
+    fn park<F>(&self, done: F)
+    where
+        F: Fn() -> bool,
+    {
+        let parked = self.shared.sleep.park(
+            || done() || self.has_work(),
+            || self.shared.timers.park_timeout(),
+        );
+
+        match parked {
+            Parked::Skipped => {}
+            Parked::Woken => self.counters().park(true),
+            Parked::TimedOut => self.counters().park(false),
+        }
+    }
+
+    /// Whether anything could be found or stolen right now, or the
+    /// scheduler is shutting down. Timers are covered by the park timeout.
+    fn has_work(&self) -> bool {
+        let shared = &self.shared;
+
+        shared.shutdown.load(Ordering::Acquire)
+            || self.workers.iter().any(|worker| !worker.is_empty())
+            || shared.injectors.iter().any(|injector| !injector.is_empty())
+            || shared
+                .stealers
+                .iter()
+                .flatten()
+                .any(|stealer| !stealer.is_empty())
+            || shared
+                .lifo_slots
+                .iter()
+                .any(|slot| slot.lock().unwrap().is_some())
+    }
+
+    fn lifo_slot(&self) -> MutexGuard<'_, Option<Box<Job>>> {
+        self.shared.lifo_slots[self.index].lock().unwrap()
+    }
+
+    fn find_task(&self) -> Option<(Box<Job>, Priority)> {
+        let task = self.lifo_slot().take();
+        if let Some(task) = task {
+            let polls = self.lifo_polls.get();
+            if polls < LIFO_BUDGET {
+                self.lifo_polls.set(polls + 1);
+                return Some((task, Priority::Normal));
+            }
+
+            // Tasks that keep waking each other would otherwise have the
+            // worker to themselves. This one queues up behind whatever
+            // waits in the injector, where peers can take it too.
+            self.shared.injectors[Priority::Normal.index()].push(task);
+            self.shared.sleep.notify_one();
+        }
+        self.lifo_polls.set(0);
+
+        self.shared
+            .timers
+            .poll(|task| self.push(task, Priority::Normal));
+
+        let tick = self.ticks.get().wrapping_add(1);
+        self.ticks.set(tick);
+
+        Priority::search_order(tick)
+            .into_iter()
+            .find_map(|priority| Some((self.find_task_at(priority)?, priority)))
+            .or_else(|| Some((self.steal_lifo_slot()?, Priority::Normal)))
+    }
+
+    /// Takes the task out of a peer's LIFO slot. Only that peer would run
+    /// it otherwise, once its current task is done, however long that
+    /// takes.
+    fn steal_lifo_slot(&self) -> Option<Box<Job>> {
+        let slots = &self.shared.lifo_slots;
+
+        (1..slots.len())
+            .map(|offset| &slots[(self.index + offset) % slots.len()])
+            .find_map(|slot| slot.lock().unwrap().take())
+    }
+
+    fn find_task_at(&self, priority: Priority) -> Option<Box<Job>> {
+        let worker = &self.workers[priority.index()];
+
+        if let Some(task) = self.pop(priority) {
+            return Some(task);
+        }
+
+        loop {
+            let steal = self.steal(priority).or_else(|| {
+                let steal = self.shared.injectors[priority.index()].steal_batch_and_pop(worker);
+                if steal.is_success() {
+                    self.shared.room.notify_all();
+                }
+                steal
+            });
+
+            match steal {
+                Steal::Success(task) => {
+                    self.counters().queue_depth(self.queue_depth());
+                    return Some(task);
+                }
+                Steal::Empty => return None,
+                Steal::Retry => hint::spin_loop(),
+            }
+        }
+    }
+
+    /// Tries the `priority` deque of the peers the victim selector picks,
+    /// in its order, and takes half of the first non-empty one.
+    fn steal(&self, priority: Priority) -> Steal<Box<Job>> {
+        let stealers = &self.shared.stealers;
+        let worker = &self.workers[priority.index()];
+        let counters = self.counters();
+        let mut selector = self.selector.borrow_mut();
+        let mut victims = self.victims.borrow_mut();
+
+        victims.clear();
+        selector.select(&mut victims);
+
+        victims
+            .iter()
+            .filter(|&&victim| victim != self.index && victim < stealers.len())
+            .map(|&victim| {
+                let steal = stealers[victim][priority.index()].steal_batch_and_pop(worker);
+                counters.steal(&steal);
+                selector.record(victim, steal.is_success());
+                steal
+            })
+            .collect()
+    }
+}
+
+/// If the current thread is a worker, or drives a `Simulation`, runs tasks
+/// until `done` holds and returns `true`. Returns `false` right away on any
+/// other thread.
+///
+/// A worker hands `register` a `Waker` first, which must be woken once
+/// `done` holds, so that the worker can park meanwhile.
+pub(crate) fn help_until<F, R>(done: F, register: R) -> bool
+where
+    F: Fn() -> bool,
+    R: FnOnce(Waker),
+{
+    match WorkerThread::current() {
+        Some(worker) => {
+            register(worker.waker());
+            worker.wait_until(done);
+            true
+        }
+        None => simulation::help_until(&done),
+    }
+}
+
+/// Cloneable handle for submitting tasks to a `Scheduler`.
+#[derive(Clone)]
+pub struct Handle {
+    shared: Arc<Shared>,
+}
+
+/// Handle that does not keep the scheduler alive, for tasks that may
+/// outlive it while waiting to be woken.
+pub(crate) struct WeakHandle {
+    shared: Weak<Shared>,
+}
+
+impl WeakHandle {
+    pub(crate) fn upgrade(&self) -> Option<Handle> {
+        self.shared.upgrade().map(|shared| Handle { shared })
+    }
+}
+
+impl Handle {
+    /// Returns the handle of the scheduler running the current thread, if
+    /// it is one of its workers.
+    pub fn current() -> Option<Handle> {
+        WorkerThread::current().map(|worker| Handle {
+            shared: worker.shared.clone(),
+        })
+    }
+
+    /// Queues a task at `Priority::Normal`. Tasks submitted from a worker
+    /// go to that worker's own deque; tasks from any other thread go to the
+    /// shared injector.
+    pub fn submit<T>(&self, task: T)
+    where
+        T: Task + Send + 'static,
+    {
+        self.submit_with_priority(task, Priority::Normal);
+    }
+
+    /// Queues a task at `priority`. If the queue is full, the scheduler's
+    /// `Backpressure` policy decides what happens to it.
+    pub fn submit_with_priority<T>(&self, task: T, priority: Priority)
+    where
+        T: Task + Send + 'static,
+    {
+        let _ = self.submit_job(Box::new(task), priority);
+    }
+
+    /// Queues a job on behalf of a caller, applying the backpressure
+    /// policy if the queue is full.
+    fn submit_job(&self, mut job: Box<Job>, priority: Priority) -> Result<(), Rejected> {
+        let worker =
+            WorkerThread::current().filter(|worker| Arc::ptr_eq(&worker.shared, &self.shared));
+
+        loop {
+            let pushed = match worker {
+                Some(worker) => worker.try_push(job, priority),
+                None => self.try_inject_job(job, priority),
+            };
+            job = match pushed {
+                Ok(()) => return Ok(()),
+                Err(job) => job,
+            };
+
+            match self.shared.backpressure {
+                Backpressure::Reject => {
+                    self.shared.rejected.fetch_add(1, Ordering::Relaxed);
+                    return Err(Rejected);
+                }
+                Backpressure::SpillToInjector => break,
+                // Once the workers are gone, there is no room to wait for.
+                Backpressure::Block if self.shared.shutdown.load(Ordering::Acquire) => break,
+                // Running queued tasks is what makes room in a worker's
+                // own deque. Finding none means the deque is empty already.
+                // Those tasks may submit more in turn, so the nesting is
+                // capped to keep the stack in bounds.
+                Backpressure::Block => match worker {
+                    Some(worker) if worker.block_depth.get() >= BLOCK_HELP_DEPTH => break,
+                    Some(worker) => {
+                        if let Some((task, priority)) = worker.find_task() {
+                            let depth = worker.block_depth.replace(worker.block_depth.get() + 1);
+                            worker.execute(task, priority);
+                            worker.block_depth.set(depth);
+                        }
+                    }
+                    // A simulation only makes room while it is stepped.
+                    None if simulation::step() => {}
+                    None => self.wait_for_room(priority),
+                },
+                Backpressure::RunInline => {
+                    match worker {
+                        Some(worker) => worker.execute(job, priority),
+                        None => self.shared.run_inline(job),
+                    }
+                    return Ok(());
+                }
+            }
+        }
+
+        self.inject(job, priority);
+        Ok(())
+    }
+
+    /// Pushes into the injector of `priority` and wakes a worker. If the
+    /// scheduler has shut down meanwhile, runs the job right here instead,
+    /// since the workers may have exited without seeing it.
+    fn inject(&self, job: Box<Job>, priority: Priority) {
+        self.shared.injectors[priority.index()].push(job);
+        self.notify_worker();
+
+        // `notify_worker` fences after the push and `Scheduler::drop` fences
+        // after setting the flag, so either this sees the flag or the final
+        // `run_orphans` of the drop sees the job.
+        if self.shared.shutdown.load(Ordering::Acquire) {
+            self.shared.run_orphans();
+        }
+    }
+
+    /// Parks the calling thread until the injector of `priority` has room
+    /// again, or the scheduler shuts down. Workers wake it whenever they
+    /// take tasks out of an injector.
+    fn wait_for_room(&self, priority: Priority) {
+        let injector = &self.shared.injectors[priority.index()];
+        let capacity = self.shared.capacity.unwrap_or(usize::MAX);
+
+        self.shared.room.park(
+            || injector.len() < capacity || self.shared.shutdown.load(Ordering::Acquire),
+            || None,
+        );
+    }
+
+    /// Pushes into the injector unless it already holds `capacity` tasks.
+    /// Producers race each other, so the bound may be overshot by a few.
+    fn try_inject_job(&self, job: Box<Job>, priority: Priority) -> Result<(), Box<Job>> {
+        let injector = &self.shared.injectors[priority.index()];
+
+        if self
+            .shared
+            .capacity
+            .is_some_and(|capacity| injector.len() >= capacity)
+        {
+            return Err(job);
+        }
+
+        self.inject(job, priority);
+        Ok(())
+    }
+
+    pub(crate) fn push_job(&self, job: Box<Job>, priority: Priority) {
+        if let Some(worker) = WorkerThread::current() {
+            if Arc::ptr_eq(&worker.shared, &self.shared) {
+                worker.push(job, priority);
+                return;
+            }
+        }
+
+        self.inject(job, priority);
+    }
+
+    /// Queues a job that was just woken. On one of this scheduler's workers
+    /// it goes into the LIFO slot, bumping any previous occupant into the
+    /// deque; from anywhere else it goes to the injector. Either way a
+    /// sleeper is woken, in case the worker stays busy.
+    pub(crate) fn push_woken_job(&self, job: Box<Job>) {
+        if let Some(worker) = WorkerThread::current() {
+            if Arc::ptr_eq(&worker.shared, &self.shared) {
+                let previous = worker.lifo_slot().replace(job);
+                match previous {
+                    Some(previous) => worker.push(previous, Priority::Normal),
+                    None => self.notify_worker(),
+                }
+                return;
+            }
+        }
+
+        self.inject_job(job);
+    }
+
+    pub(crate) fn inject_job(&self, job: Box<Job>) {
+        self.inject(job, Priority::Normal);
+    }
+
+    /// Shuts the scheduler down without waiting for any workers, for a
+    /// `Simulation` whose workers are gone already. Runs whatever is still
+    /// queued; from then on, tasks submitted through any handle run on the
+    /// submitting thread.
+    pub(crate) fn close(&self) {
+        self.shared.shutdown.store(true, Ordering::Release);
+        self.shared.sleep.notify_all();
+        self.shared.room.notify_all();
+
+        self.shared.run_orphans();
+    }
+
+    /// Wakes one parked worker, if any.
+    pub(crate) fn notify_worker(&self) {
+        self.shared.sleep.notify_one();
+    }
+
+    pub(crate) fn timers(&self) -> &Timers {
+        &self.shared.timers
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakHandle {
+        WeakHandle {
+            shared: Arc::downgrade(&self.shared),
+        }
+    }
+
+    /// Runs `f` on one of the workers and waits for it, so that `join` and
+    /// friends called from `f` run in parallel. Unlike `spawn`, `f` may
+    /// borrow from the caller. Panics in `f` are resumed on the caller.
+    pub fn install<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R + Send,
+        R: Send,
+    {
+        if let Some(worker) = WorkerThread::current() {
+            if Arc::ptr_eq(&worker.shared, &self.shared) {
+                return f();
+            }
+        }
+
+        let job = StackJob::new(f, LockLatch::new());
+        self.inject_job(Box::new(unsafe { job.as_job_ref() }));
+        if !help_until(|| job.latch.probe(), |waker| job.latch.register(waker)) {
+            job.latch.wait();
+        }
+
+        job.into_result()
+            .unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+
+    /// Runs `f` on the pool at `Priority::Normal` and returns a handle to
+    /// its result.
+    pub fn spawn<F, R>(&self, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.spawn_with_priority(f, Priority::Normal)
+    }
+
+    pub fn spawn_with_priority<F, R>(&self, f: F, priority: Priority) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.spawn_job(f, priority, None)
+    }
+
+    /// Like `spawn`, but a worker that gets to the task after `token` was
+    /// cancelled skips it, and `join` then fails with a `Cancelled`
+    /// payload. Cancelling the token does not stop the task once it runs.
+    pub fn spawn_with_token<F, R>(&self, f: F, token: CancellationToken) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.spawn_job(f, Priority::Normal, Some(token))
+    }
+
+    fn spawn_job<F, R>(
+        &self,
+        f: F,
+        priority: Priority,
+        token: Option<CancellationToken>,
+    ) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let packet = Packet::new();
+        let handle = JoinHandle::new(packet.clone());
+        let task = Box::new(Spawned::new(f, packet.clone(), token));
+
+        if task.is_cancelled() {
+            task.cancel();
+        } else if let Err(rejected) = self.submit_job(task, priority) {
+            // Dropping the task already marked it abandoned; the rejection
+            // says why, and nobody can have taken the result yet.
+            packet.complete(Err(Box::new(rejected)));
+        }
+
+        handle
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.shared.stealers.len()
+    }
+}
+
+/// Work-stealing thread pool.
+///
+/// Each worker owns a `WorkStealingDeque` per `Priority`, runs its own tasks
+/// newest first and, once it runs dry, steals the oldest tasks of the peers
+/// its `VictimSelector` picks, random ones unless `Builder::victim_selector`
+/// says otherwise. Tasks submitted from other threads wait in shared
+/// injector queues, again one per priority, that idle workers drain in
+/// batches.
+/// Higher priorities are searched first, see `Priority` for how lower ones
+/// still get their turn. Workers that find nothing to do spin briefly, then
+/// yield, then park until new work shows up.
+///
+/// Dropping the scheduler shuts it down gracefully; timers that have not
+/// fired by then are dropped. Handles that outlive it run whatever they
+/// submit on the calling thread.
+pub struct Scheduler {
+    handle: Handle,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+pub type ThreadPool = Scheduler;
+
+/// Configures a `Scheduler` before starting it.
+pub struct Builder {
+    num_threads: usize,
+    clock: Arc<dyn Clock>,
+    selector: Box<SelectorFactory>,
+    panic_handler: Option<Box<PanicHandler>>,
+    capacity: Option<usize>,
+    backpressure: Backpressure,
+}
+
+impl Builder {
+    pub fn new(num_threads: usize) -> Self {
+        Self {
+            num_threads,
+            clock: Arc::new(SystemClock::new()),
+            selector: Box::new(random_selectors),
+            panic_handler: None,
+            capacity: None,
+            backpressure: Backpressure::default(),
+        }
+    }
+
+    /// Sets how workers pick peers to steal from. Every scheduler built
+    /// gets a clone of `selector`, bound to its number of workers, and
+    /// every worker a clone of that. Defaults to a `RandomSelector`.
+    pub fn victim_selector<S>(mut self, selector: S) -> Self
+    where
+        S: VictimSelector + Clone + 'static,
+    {
+        self.selector = Box::new(move |num_workers| {
+            let mut selector = selector.clone();
+            selector.bind(num_workers);
+
+            (0..num_workers)
+                .map(|_| Box::new(selector.clone()) as Box<dyn VictimSelector>)
+                .collect()
+        });
+        self
+    }
+
+    /// Sets the clock that timers run on. Defaults to a `SystemClock`.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets what happens with the payload of a task that panicked on a
+    /// worker. The worker survives either way, and by default the payload
+    /// is dropped once the panic hook has printed it. Tasks started with
+    /// `spawn` or `spawn_future` never get here, their join handle gets the
+    /// panic instead.
+    pub fn panic_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(Box<dyn Any + Send>) + Send + Sync + 'static,
+    {
+        self.panic_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Bounds each worker deque to `capacity` tasks, and the injectors to
+    /// as many tasks submitted from outside the pool. What happens to tasks
+    /// that do not fit is up to the `backpressure` policy. Unbounded by
+    /// default.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "queue capacity must be positive");
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Sets what happens to tasks submitted while their queue is full.
+    /// Defaults to `Backpressure::SpillToInjector`.
+    pub fn backpressure(mut self, policy: Backpressure) -> Self {
+        self.backpressure = policy;
+        self
+    }
+
+    pub fn build(self) -> Scheduler {
+        let num_threads = self.num_threads;
+        let (shared, workers, selectors) = self.prepare();
+
+        let threads = workers
+            .into_iter()
+            .zip(selectors)
+            .enumerate()
+            .map(|(index, (workers, mut selector))| {
+                let shared = shared.clone();
+
+                thread::Builder::new()
+                    .name(format!("memo-worker-{}", index))
+                    .spawn(move || {
+                        selector.start(index, num_threads);
+                        WorkerThread::new(index, workers, shared, selector).run();
+                    })
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+
+        Scheduler {
+            handle: Handle { shared },
+            threads,
+        }
+    }
+
+    /// Builds a `Simulation` instead, whose workers all take turns on the
+    /// calling thread in an order drawn from `seed`. Victims are picked at
+    /// random from the same seed, so the victim selector set here is not
+    /// used.
+    pub fn build_simulation(mut self, seed: u64) -> Simulation {
+        self.selector = Box::new(random_selectors);
+        let num_threads = self.num_threads;
+        let (shared, workers, _) = self.prepare();
+        let mut rng = XorShift::new(seed);
+
+        let workers = workers
+            .into_iter()
+            .enumerate()
+            .map(|(index, workers)| {
+                let mut selector = RandomSelector::with_seed(rng.next_u64());
+                selector.start(index, num_threads);
+                WorkerThread::new(index, workers, shared.clone(), Box::new(selector))
+            })
+            .collect();
+
+        Simulation::new(seed, rng, Handle { shared }, workers)
+    }
+
+    /// Creates the state shared by all workers and each worker's deques.
+    fn prepare(self) -> (Arc<Shared>, Vec<Deques>, Vec<Box<dyn VictimSelector>>) {
+        let Self {
+            num_threads,
+            clock,
+            selector,
+            panic_handler,
+            capacity,
+            backpressure,
+        } = self;
+        assert!(num_threads > 0, "a scheduler needs at least one worker");
+        let selectors = selector(num_threads);
+
+        let workers: Vec<Deques> = (0..num_threads)
+            .map(|_| {
+                array::from_fn(|_| match capacity {
+                    Some(capacity) => WorkStealingDeque::bounded(capacity).0,
+                    None => WorkStealingDeque::new(DEQUE_CAPACITY).0,
+                })
+            })
+            .collect();
+        let stealers = workers
+            .iter()
+            .map(|workers| workers.each_ref().map(Worker::stealer))
+            .collect();
+
+        let shared = Arc::new(Shared {
+            stealers,
+            injectors: array::from_fn(|_| Injector::new()),
+            lifo_slots: (0..num_threads).map(|_| Mutex::new(None)).collect(),
+            timers: Timers::new(clock.clone()),
+            sleep: Sleep::new(),
+            room: Sleep::new(),
+            strategy: selectors[0].name(),
+            counters: (0..num_threads)
+                .map(|_| WorkerCounters::default())
+                .collect(),
+            panic_handler,
+            capacity,
+            backpressure,
+            rejected: AtomicU64::new(0),
+            shutdown: AtomicBool::new(false),
+        });
+        clock.subscribe(Waker::from(Arc::new(ClockWaker(Arc::downgrade(&shared)))));
+
+        (shared, workers, selectors)
+    }
+}
+
+fn random_selectors(num_workers: usize) -> Vec<Box<dyn VictimSelector>> {
+    (0..num_workers)
+        .map(|_| Box::new(RandomSelector::new()) as Box<dyn VictimSelector>)
+        .collect()
+}
+
+impl Scheduler {
+    pub fn new(num_threads: usize) -> Self {
+        Builder::new(num_threads).build()
+    }
+
+    pub fn handle(&self) -> Handle {
+        self.handle.clone()
+    }
+
+    pub fn submit<T>(&self, task: T)
+    where
+        T: Task + Send + 'static,
+    {
+        self.handle.submit(task);
+    }
+
+    pub fn submit_with_priority<T>(&self, task: T, priority: Priority)
+    where
+        T: Task + Send + 'static,
+    {
+        self.handle.submit_with_priority(task, priority);
+    }
+
+    pub fn spawn<F, R>(&self, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.handle.spawn(f)
+    }
+
+    pub fn spawn_with_priority<F, R>(&self, f: F, priority: Priority) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.handle.spawn_with_priority(f, priority)
+    }
+
+    pub fn spawn_with_token<F, R>(&self, f: F, token: CancellationToken) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.handle.spawn_with_token(f, token)
+    }
+
+    pub fn install<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R + Send,
+        R: Send,
+    {
+        self.handle.install(f)
+    }
+
+    pub fn spawn_future<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.handle.spawn_future(future)
+    }
+
+    pub fn block_on<F>(&self, future: F) -> F::Output
+    where
+        F: Future,
+    {
+        self.handle.block_on(future)
+    }
+
+    pub fn spawn_after<T>(&self, delay: Duration, task: T) -> TimerHandle
+    where
+        T: Task + Send + 'static,
+    {
+        self.handle.spawn_after(delay, task)
+    }
+
+    pub fn spawn_every<F>(&self, interval: Duration, f: F) -> TimerHandle
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.handle.spawn_every(interval, f)
+    }
+
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        self.handle.scope(f)
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.handle.num_threads()
+    }
+
+    /// Snapshot of every worker's counters.
+    pub fn metrics(&self) -> Metrics {
+        let shared = &self.handle.shared;
+
+        Metrics {
+            workers: shared
+                .counters
+                .iter()
+                .map(WorkerCounters::snapshot)
+                .collect(),
+            injector_depth: shared.injectors.iter().map(Injector::len).sum(),
+            rejected: shared.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Steal attempts and successes of all workers so far, to compare
+    /// victim selectors.
+    pub fn steal_stats(&self) -> StealStats {
+        let total = self.metrics().total();
+
+        StealStats {
+            strategy: self.handle.shared.strategy,
+            attempts: total.steals + total.failed_steals + total.steal_retries,
+            successes: total.steals,
+        }
+    }
+
+    /// Waits for every queued task to run, then stops and joins all
+    /// workers.
+    pub fn shutdown(self) {
+        drop(self);
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.handle.shared.shutdown.store(true, Ordering::Release);
+        self.handle.shared.sleep.notify_all();
+        self.handle.shared.room.notify_all();
+
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+
+        // Tasks injected while the workers were on their way out.
+        self.handle.shared.run_orphans();
+    }
+}
+
+#[cfg(test)]
+mod scheduler_test {
+    use super::*;
+    use crate::work_stealing::{cancel::Cancelled, join::join};
+    use std::sync::{atomic::AtomicUsize, mpsc, Mutex};
+
+    /// Submits `depth` more levels of itself from inside the pool.
+    struct Tree {
+        depth: usize,
+        count: Arc<AtomicUsize>,
+    }
+
+    impl Task for Tree {
+        fn execute(self: Box<Self>) {
+            self.count.fetch_add(1, Ordering::Relaxed);
+
+            if self.depth > 0 {
+                let handle = Handle::current().unwrap();
+                handle.submit(Tree {
+                    depth: self.depth - 1,
+                    count: self.count.clone(),
+                });
+                handle.submit(Tree {
+                    depth: self.depth - 1,
+                    count: self.count,
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn test_runs_submitted_tasks() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let scheduler = Scheduler::new(4);
+
+        for _ in 0..1000 {
+            let count = count.clone();
+            scheduler.submit(move || {
+                count.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        scheduler.shutdown();
+
+        assert_eq!(count.load(Ordering::Relaxed), 1000);
+    }
+
+    #[test]
+    fn test_tasks_submitted_from_workers() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let scheduler = Scheduler::new(3);
+
+        scheduler.submit(Tree {
+            depth: 10,
+            count: count.clone(),
+        });
+        scheduler.shutdown();
+
+        assert_eq!(count.load(Ordering::Relaxed), (1 << 11) - 1);
+    }
+
+    #[test]
+    fn test_runs_on_worker_threads() {
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let scheduler = Scheduler::new(2);
+
+        for _ in 0..10 {
+            let names = names.clone();
+            scheduler.submit(move || {
+                let name = thread::current().name().unwrap_or_default().to_string();
+                names.lock().unwrap().push(name);
+            });
+        }
+        drop(scheduler);
+
+        let names = names.lock().unwrap();
+        assert_eq!(names.len(), 10);
+        assert!(names.iter().all(|name| name.starts_with("memo-worker-")));
+    }
+
+    #[test]
+    fn test_submit_from_many_threads() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let scheduler = Scheduler::new(2);
+
+        let producers: Vec<_> = (0..4)
+            .map(|_| {
+                let handle = scheduler.handle();
+                let count = count.clone();
+                thread::spawn(move || {
+                    for _ in 0..250 {
+                        let count = count.clone();
+                        handle.submit(move || {
+                            count.fetch_add(1, Ordering::Relaxed);
+                        });
+                    }
+                })
+            })
+            .collect();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        scheduler.shutdown();
+
+        assert_eq!(count.load(Ordering::Relaxed), 1000);
+    }
+
+    #[test]
+    fn test_spawn_returns_value() {
+        let scheduler = Scheduler::new(2);
+
+        let handles: Vec<_> = (0..100).map(|i| scheduler.spawn(move || i * 2)).collect();
+        let results: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect();
+
+        assert_eq!(results, (0..100).map(|i| i * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_spawn_moves_result_out() {
+        let scheduler = Scheduler::new(1);
+        let buffer = vec![1, 2, 3];
+
+        let handle = scheduler.spawn(move || buffer.into_iter().rev().collect::<Vec<_>>());
+
+        assert_eq!(handle.join().unwrap(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_spawn_reports_panic() {
+        let scheduler = Scheduler::new(1);
+
+        let handle = scheduler.spawn(|| -> u32 { panic!("boom") });
+        let payload = handle.join().unwrap_err();
+
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+        assert_eq!(scheduler.spawn(|| 7).join().unwrap(), 7);
+    }
+
+    #[test]
+    fn test_worker_survives_panicking_task() {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let scheduler = Builder::new(1)
+            .panic_handler(move |payload| {
+                let message = payload.downcast_ref::<&str>().copied();
+                sender.lock().unwrap().send(message).unwrap();
+            })
+            .build();
+
+        scheduler.submit(|| panic!("boom"));
+        assert_eq!(receiver.recv().unwrap(), Some("boom"));
+
+        // The same, and only, worker is still there to run this.
+        assert_eq!(scheduler.spawn(|| 7).join().unwrap(), 7);
+        assert_eq!(scheduler.metrics().total().panics, 1);
+    }
+
+    #[test]
+    fn test_panicking_handler_is_contained() {
+        let scheduler = Builder::new(1).panic_handler(|_| panic!("handler")).build();
+
+        scheduler.submit(|| panic!("boom"));
+        scheduler.submit(|| panic!("boom"));
+
+        assert_eq!(scheduler.spawn(|| 7).join().unwrap(), 7);
+    }
+
+    /// Occupies the only worker of `scheduler` until the returned sender
+    /// is dropped.
+    fn block_worker(scheduler: &Scheduler) -> mpsc::Sender<()> {
+        let (started, running) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+
+        scheduler.submit(move || {
+            started.send(()).unwrap();
+            let _ = released.recv();
+        });
+        running.recv().unwrap();
+
+        release
+    }
+
+    #[test]
+    fn test_reject_when_full() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let scheduler = Builder::new(1)
+            .queue_capacity(1)
+            .backpressure(Backpressure::Reject)
+            .build();
+        let release = block_worker(&scheduler);
+
+        for _ in 0..2 {
+            let count = count.clone();
+            scheduler.submit(move || {
+                count.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        let payload = scheduler.spawn(|| 7).join().unwrap_err();
+
+        assert_eq!(payload.downcast_ref::<Rejected>(), Some(&Rejected));
+        assert_eq!(scheduler.metrics().rejected, 2);
+
+        drop(release);
+        scheduler.shutdown();
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_block_until_room() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let scheduler = Builder::new(1)
+            .queue_capacity(1)
+            .backpressure(Backpressure::Block)
+            .build();
+        let release = block_worker(&scheduler);
+
+        let submitter = {
+            let handle = scheduler.handle();
+            let count = count.clone();
+            thread::spawn(move || {
+                for _ in 0..5 {
+                    let count = count.clone();
+                    handle.submit(move || {
+                        count.fetch_add(1, Ordering::Relaxed);
+                    });
+                }
+            })
+        };
+
+        while scheduler.metrics().injector_depth == 0 {
+            thread::yield_now();
+        }
+        assert!(!submitter.is_finished());
+        assert_eq!(scheduler.metrics().injector_depth, 1);
+
+        drop(release);
+        submitter.join().unwrap();
+        scheduler.shutdown();
+        assert_eq!(count.load(Ordering::Relaxed), 5);
+    }
+
+    #[test]
+    fn test_shutdown_releases_blocked_producer() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let scheduler = Builder::new(1)
+            .queue_capacity(1)
+            .backpressure(Backpressure::Block)
+            .build();
+        let release = block_worker(&scheduler);
+
+        let submitter = {
+            let handle = scheduler.handle();
+            let count = count.clone();
+            thread::spawn(move || {
+                for _ in 0..3 {
+                    let count = count.clone();
+                    handle.submit(move || {
+                        count.fetch_add(1, Ordering::Relaxed);
+                    });
+                }
+            })
+        };
+        while scheduler.metrics().injector_depth == 0 {
+            thread::yield_now();
+        }
+
+        // The drop waits for the blocked worker, but the producer must get
+        // going again before that.
+        let dropper = thread::spawn(move || drop(scheduler));
+        submitter.join().unwrap();
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+
+        drop(release);
+        dropper.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_blocked_producer_uses_no_cpu() {
+        let scheduler = Builder::new(1)
+            .queue_capacity(1)
+            .backpressure(Backpressure::Block)
+            .build();
+        let release = block_worker(&scheduler);
+        let (sender, receiver) = mpsc::channel();
+
+        let submitter = {
+            let handle = scheduler.handle();
+            thread::spawn(move || {
+                let link = std::fs::read_link("/proc/thread-self").unwrap();
+                sender.send(format!("/proc/{}", link.display())).unwrap();
+
+                for _ in 0..2 {
+                    handle.submit(|| {});
+                }
+            })
+        };
+        let threads = [receiver.recv().unwrap()];
+        while scheduler.metrics().injector_depth == 0 {
+            thread::yield_now();
+        }
+
+        let ticks = ticks_while_idle(&threads);
+        assert!(ticks <= 5, "blocked producer used {} ticks", ticks);
+
+        drop(release);
+        submitter.join().unwrap();
+    }
+
+    #[test]
+    fn test_block_runs_tasks_on_worker() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let scheduler = Builder::new(1)
+            .queue_capacity(2)
+            .backpressure(Backpressure::Block)
+            .build();
+
+        let handle = scheduler.handle();
+        let tasks = count.clone();
+        scheduler
+            .spawn(move || {
+                for _ in 0..20 {
+                    handle.submit(Tree {
+                        depth: 0,
+                        count: tasks.clone(),
+                    });
+                }
+            })
+            .join()
+            .unwrap();
+
+        assert!(scheduler.metrics().total().max_queue_depth <= 2);
+        scheduler.shutdown();
+        assert_eq!(count.load(Ordering::Relaxed), 20);
+    }
+
+    /// Submits the next link first and a leaf after it, so under `Block`
+    /// the leaf finds the deque full of the next link.
+    struct Chain {
+        length: usize,
+        count: Arc<AtomicUsize>,
+    }
+
+    impl Task for Chain {
+        fn execute(self: Box<Self>) {
+            self.count.fetch_add(1, Ordering::Relaxed);
+
+            if self.length > 0 {
+                let handle = Handle::current().unwrap();
+                let count = self.count.clone();
+                handle.submit(Chain {
+                    length: self.length - 1,
+                    count: self.count,
+                });
+                handle.submit(move || {
+                    count.fetch_add(1, Ordering::Relaxed);
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn test_block_on_worker_does_not_recurse_deeply() {
+        const LENGTH: usize = 100_000;
+        let count = Arc::new(AtomicUsize::new(0));
+        let scheduler = Builder::new(1)
+            .queue_capacity(1)
+            .backpressure(Backpressure::Block)
+            .build();
+
+        scheduler.submit(Chain {
+            length: LENGTH,
+            count: count.clone(),
+        });
+        while count.load(Ordering::Relaxed) < 2 * LENGTH + 1 {
+            thread::yield_now();
+        }
+
+        assert!(scheduler.metrics().total().max_queue_depth <= 1);
+    }
+
+    #[test]
+    fn test_spill_to_injector() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let scheduler = Builder::new(1).queue_capacity(1).build();
+
+        scheduler.submit(Tree {
+            depth: 10,
+            count: count.clone(),
+        });
+        while count.load(Ordering::Relaxed) < 11 {
+            thread::yield_now();
+        }
+
+        assert!(scheduler.metrics().total().max_queue_depth <= 1);
+    }
+
+    #[test]
+    fn test_run_inline_when_full() {
+        let scheduler = Builder::new(1)
+            .queue_capacity(1)
+            .backpressure(Backpressure::RunInline)
+            .build();
+        let release = block_worker(&scheduler);
+        let (sender, receiver) = mpsc::channel();
+
+        for _ in 0..2 {
+            let sender = sender.clone();
+            scheduler.submit(move || sender.send(thread::current().id()).unwrap());
+        }
+
+        assert_eq!(receiver.recv().unwrap(), thread::current().id());
+        drop(release);
+        assert_ne!(receiver.recv().unwrap(), thread::current().id());
+    }
+
+    #[test]
+    fn test_cancelled_tasks_are_skipped() {
+        let scheduler = Scheduler::new(1);
+        let release = block_worker(&scheduler);
+        let ran = Arc::new(AtomicUsize::new(0));
+        let query = CancellationToken::new();
+
+        let fan_out: Vec<_> = (0..8)
+            .map(|_| {
+                let ran = ran.clone();
+                scheduler.spawn_with_token(
+                    move || ran.fetch_add(1, Ordering::Relaxed),
+                    query.child_token(),
+                )
+            })
+            .collect();
+        let other = scheduler.spawn_with_token(|| 7, CancellationToken::new());
+
+        query.cancel();
+        let late = scheduler.spawn_with_token(|| 8, query.child_token());
+        drop(release);
+
+        for handle in fan_out.into_iter().chain([late]) {
+            let payload = handle.join().unwrap_err();
+            assert_eq!(payload.downcast_ref::<Cancelled>(), Some(&Cancelled));
+        }
+        assert_eq!(other.join().unwrap(), 7);
+        assert_eq!(ran.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_running_task_checks_token() {
+        let scheduler = Scheduler::new(2);
+        let token = CancellationToken::new();
+        let (started, running) = mpsc::channel();
+
+        let handle = {
+            let check = token.clone();
+            scheduler.spawn_with_token(
+                move || {
+                    started.send(()).unwrap();
+                    let mut rounds = 0;
+                    while !check.is_cancelled() {
+                        rounds += 1;
+                        thread::yield_now();
+                    }
+                    rounds
+                },
+                token.clone(),
+            )
+        };
+
+        running.recv().unwrap();
+        token.cancel();
+        assert!(handle.join().is_ok());
+    }
+
+    #[test]
+    fn test_join_inside_pool() {
+        let scheduler = Scheduler::new(1);
+
+        let handle = scheduler.spawn(|| {
+            let handle = Handle::current().unwrap();
+            let inner: Vec<_> = (0..10).map(|i| handle.spawn(move || i)).collect();
+
+            inner
+                .into_iter()
+                .map(|inner| inner.join().unwrap())
+                .sum::<i32>()
+        });
+
+        assert_eq!(handle.join().unwrap(), 45);
+    }
+
+    #[test]
+    fn test_high_priority_runs_first() {
+        let scheduler = Scheduler::new(1);
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        // Keeps the only worker busy until everything is queued.
+        scheduler.submit(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        });
+        started_rx.recv().unwrap();
+
+        for priority in [Priority::Background, Priority::High] {
+            for _ in 0..10 {
+                let order = order.clone();
+                scheduler
+                    .submit_with_priority(move || order.lock().unwrap().push(priority), priority);
+            }
+        }
+        release_tx.send(()).unwrap();
+        scheduler.shutdown();
+
+        // Aging may let at most one background task cut in line.
+        let order = order.lock().unwrap();
+        let last_high = order.iter().rposition(|&p| p == Priority::High).unwrap();
+        let early = order[..last_high]
+            .iter()
+            .filter(|&&p| p == Priority::Background)
+            .count();
+        assert_eq!(order.len(), 20);
+        assert!(early <= 1, "{:?}", order);
+    }
+
+    /// Keeps resubmitting itself at high priority until `stop` is set.
+    struct Flood {
+        stop: Arc<AtomicBool>,
+    }
+
+    impl Task for Flood {
+        fn execute(self: Box<Self>) {
+            if !self.stop.load(Ordering::Relaxed) {
+                Handle::current()
+                    .unwrap()
+                    .submit_with_priority(*self, Priority::High);
+            }
+        }
+    }
+
+    #[test]
+    fn test_background_is_not_starved() {
+        let scheduler = Scheduler::new(1);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let background = {
+            let stop = stop.clone();
+            scheduler.spawn_with_priority(
+                move || stop.store(true, Ordering::Relaxed),
+                Priority::Background,
+            )
+        };
+        scheduler.submit_with_priority(Flood { stop }, Priority::High);
+
+        background.join().unwrap();
+    }
+
+    #[test]
+    fn test_join_keeps_task_priority() {
+        let scheduler = Scheduler::new(2);
+
+        let priorities = scheduler
+            .spawn_with_priority(
+                || {
+                    crate::work_stealing::join::join(
+                        || WorkerThread::current().unwrap().priority(),
+                        || WorkerThread::current().unwrap().priority(),
+                    )
+                },
+                Priority::Background,
+            )
+            .join()
+            .unwrap();
+
+        assert_eq!(priorities, (Priority::Background, Priority::Background));
+    }
+
+    #[test]
+    fn test_wakes_parked_workers() {
+        let scheduler = Scheduler::new(3);
+        let (sender, receiver) = mpsc::channel();
+
+        // Each round gives the workers time to go back to sleep, so a lost
+        // wakeup shows up as a timeout.
+        for round in 0..200 {
+            let sender = sender.clone();
+            scheduler.submit(move || sender.send(round).unwrap());
+
+            assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(round));
+        }
+    }
+
+    /// CPU time a thread has used so far, in clock ticks.
+    #[cfg(target_os = "linux")]
+    fn thread_cpu_ticks(thread: &str) -> u64 {
+        let stat = std::fs::read_to_string(format!("{}/stat", thread)).unwrap();
+        // The thread name may contain spaces, so fields are counted from
+        // the parenthesis closing it. utime and stime come 12th and 13th.
+        let fields: Vec<&str> = stat[stat.rfind(')').unwrap() + 2..].split(' ').collect();
+
+        fields[11].parse::<u64>().unwrap() + fields[12].parse::<u64>().unwrap()
+    }
+
+    /// `/proc` entries of every worker thread of `scheduler`.
+    #[cfg(target_os = "linux")]
+    fn worker_threads(scheduler: &Scheduler) -> Vec<String> {
+        let num_threads = scheduler.num_threads();
+        let barrier = Arc::new(std::sync::Barrier::new(num_threads));
+
+        // Blocking on the barrier makes sure every worker takes one task.
+        let threads: Vec<_> = (0..num_threads)
+            .map(|_| {
+                let barrier = barrier.clone();
+                scheduler.spawn(move || {
+                    barrier.wait();
+                    let link = std::fs::read_link("/proc/thread-self").unwrap();
+                    format!("/proc/{}", link.display())
+                })
+            })
+            .collect();
+
+        threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect()
+    }
+
+    /// CPU ticks `threads` use over half a second, after a short pause to
+    /// let them settle.
+    #[cfg(target_os = "linux")]
+    fn ticks_while_idle(threads: &[String]) -> u64 {
+        thread::sleep(Duration::from_millis(100));
+        let before: u64 = threads.iter().map(|thread| thread_cpu_ticks(thread)).sum();
+        thread::sleep(Duration::from_millis(500));
+        let after: u64 = threads.iter().map(|thread| thread_cpu_ticks(thread)).sum();
+
+        after - before
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_idle_workers_use_no_cpu() {
+        let scheduler = Scheduler::new(4);
+        let threads = worker_threads(&scheduler);
+
+        // Busy workers would burn up to 200 ticks here at the usual 100 Hz.
+        let ticks = ticks_while_idle(&threads);
+        assert!(ticks <= 5, "idle workers used {} ticks", ticks);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_waiting_workers_use_no_cpu() {
+        let scheduler = Arc::new(Scheduler::new(2));
+        let threads = worker_threads(&scheduler);
+        let other = Scheduler::new(1);
+        let (release, released) = mpsc::channel::<()>();
+        let (release_other, released_other) = mpsc::channel::<()>();
+
+        // One worker waits for the half of a `join` the other one stole,
+        // which blocks; then for a task of another pool.
+        let joining = {
+            let scheduler = scheduler.clone();
+            thread::spawn(move || {
+                scheduler.install(|| {
+                    join(
+                        || thread::sleep(Duration::from_millis(50)),
+                        move || released.recv().unwrap(),
+                    )
+                })
+            })
+        };
+        let blocked = other.spawn(move || released_other.recv().unwrap());
+        let waiting = scheduler.spawn(move || blocked.join().unwrap());
+
+        let ticks = ticks_while_idle(&threads);
+        assert!(ticks <= 5, "waiting workers used {} ticks", ticks);
+
+        release.send(()).unwrap();
+        release_other.send(()).unwrap();
+        joining.join().unwrap();
+        waiting.join().unwrap();
+    }
+
+    /// Pushes tasks onto one worker's deque, then blocks that worker until
+    /// peers have stolen and run all of them.
+    fn force_steals(scheduler: &Scheduler) {
+        const THREADS: usize = 3;
+        let barrier = Arc::new(std::sync::Barrier::new(THREADS));
+
+        scheduler
+            .spawn(move || {
+                let handle = Handle::current().unwrap();
+                for _ in 1..THREADS {
+                    let barrier = barrier.clone();
+                    handle.submit(move || {
+                        barrier.wait();
+                    });
+                }
+                barrier.wait();
+            })
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_victim_selectors() {
+        use crate::work_stealing::victim::{
+            LastSuccessfulSelector, LocalitySelector, RoundRobinSelector,
+        };
+
+        fn check<S: VictimSelector + Clone + 'static>(selector: S, name: &str) {
+            let scheduler = Builder::new(3).victim_selector(selector).build();
+            force_steals(&scheduler);
+
+            let stats = scheduler.steal_stats();
+            assert_eq!(stats.strategy, name);
+            assert!(stats.successes >= 1, "{:?}", stats);
+            assert!(stats.attempts >= stats.successes);
+        }
+
+        check(RandomSelector::new(), "random");
+        check(RoundRobinSelector::new(), "round-robin");
+        check(LastSuccessfulSelector::new(), "last-successful");
+        check(LocalitySelector::new(), "locality");
+    }
+
+    #[test]
+    fn test_metrics_count_local_work() {
+        let scheduler = Scheduler::new(1);
+        let count = Arc::new(AtomicUsize::new(0));
+
+        {
+            let count = count.clone();
+            scheduler.spawn(move || {
+                let handle = Handle::current().unwrap();
+                for _ in 0..100 {
+                    let count = count.clone();
+                    handle.submit(move || {
+                        count.fetch_add(1, Ordering::Relaxed);
+                    });
+                }
+            });
+        }
+        while count.load(Ordering::Relaxed) < 100 {
+            thread::yield_now();
+        }
+
+        let metrics = scheduler.metrics();
+        let worker = metrics.workers[0];
+        assert_eq!(metrics.workers.len(), 1);
+        assert_eq!(worker.pushes, 100);
+        assert_eq!(worker.pops, 100);
+        assert_eq!(worker.max_queue_depth, 100);
+        assert_eq!(worker.steals, 0);
+        assert!(worker.busy_time > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_metrics_count_steals_and_parks() {
+        let scheduler = Scheduler::new(3);
+        force_steals(&scheduler);
+
+        // Workers fall asleep between tasks, and parks only show up once
+        // a new task wakes them.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while scheduler.metrics().total().unparks < 1 {
+            assert!(Instant::now() < deadline, "no worker was woken");
+            thread::sleep(Duration::from_millis(5));
+            scheduler.spawn(|| ()).join().unwrap();
+        }
+
+        let total = scheduler.metrics().total();
+        assert!(total.parks >= total.unparks);
+        assert!(total.steals >= 1);
+        assert!(total.idle_time > Duration::ZERO);
+        assert_eq!(scheduler.metrics().injector_depth, 0);
+
+        let stats = scheduler.steal_stats();
+        assert_eq!(stats.successes, total.steals);
+        assert!(stats.attempts >= total.steals + total.failed_steals);
+    }
+
+    #[test]
+    fn test_current_handle_outside_pool() {
+        assert!(Handle::current().is_none());
+    }
+
+    #[test]
+    fn test_handle_outlives_scheduler() {
+        let scheduler = Scheduler::new(2);
+        let handle = scheduler.handle();
+        drop(scheduler);
+
+        assert_eq!(handle.spawn(|| 1).join().unwrap(), 1);
+        assert_eq!(handle.install(|| 2), 2);
+
+        let count = AtomicUsize::new(0);
+        handle.scope(|scope| {
+            for _ in 0..3 {
+                scope.spawn(|_| {
+                    count.fetch_add(1, Ordering::Relaxed);
+                });
+            }
+        });
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+    }
+}
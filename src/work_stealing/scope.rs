@@ -0,0 +1,224 @@
+use std::{
+    any::Any,
+    marker::PhantomData,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    task::Waker,
+};
+
+use super::{
+    priority::Priority,
+    schedule::Task,
+    scheduler::{self, Handle, Job, WorkerThread},
+};
+
+/// Bookkeeping shared by a scope and every task spawned in it. Tasks hold
+/// their own reference, so finishing one never touches the `Scope` itself,
+/// which lives on the stack of the thread that called `scope`.
+struct ScopeData {
+    pending: AtomicUsize,
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+    /// Waker of a worker waiting for the scope, if any.
+    waker: Mutex<Option<Waker>>,
+    done: Condvar,
+}
+
+impl ScopeData {
+    fn complete(&self, result: std::thread::Result<()>) {
+        if let Err(payload) = result {
+            self.panic.lock().unwrap().get_or_insert(payload);
+        }
+
+        if self.pending.fetch_sub(1, Ordering::AcqRel) == 1 {
+            let waker = self.waker.lock().unwrap().take();
+            self.done.notify_all();
+
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pending.load(Ordering::Acquire) == 0
+    }
+
+    fn wait(&self) {
+        let register = |waker| *self.waker.lock().unwrap() = Some(waker);
+        if scheduler::help_until(|| self.is_done(), register) {
+            return;
+        }
+
+        let mut waker = self.waker.lock().unwrap();
+        while !self.is_done() {
+            waker = self.done.wait(waker).unwrap();
+        }
+    }
+}
+
+/// A scope to spawn tasks that may borrow anything outliving `'env`.
+///
+/// Created by `Scheduler::scope`, which does not return until every task
+/// spawned in the scope has finished.
+pub struct Scope<'scope, 'env: 'scope> {
+    handle: Handle,
+    data: Arc<ScopeData>,
+    priority: Priority,
+    _scope: PhantomData<&'scope mut &'scope ()>,
+    _env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Spawns a task onto the pool, at the priority of the task that opened
+    /// the scope. The task gets the scope back, so it can spawn more tasks
+    /// into it. A panic in the task is resumed by `scope`
+    /// once everything else has finished.
+    pub fn spawn<F>(&'scope self, f: F)
+    where
+        F: FnOnce(&'scope Scope<'scope, 'env>) + Send + 'scope,
+    {
+        let data = self.data.clone();
+        data.pending.fetch_add(1, Ordering::Relaxed);
+
+        let task: Box<dyn Task + Send + 'scope> = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| f(self)));
+            data.complete(result);
+        });
+
+        // `Handle::scope` waits for the task before anything it borrows can
+        // go away, so it may pose as `'static` while it is queued.
+        let task: Box<Job> = unsafe { mem::transmute(task) };
+        self.handle.push_job(task, self.priority);
+    }
+}
+
+impl Handle {
+    /// Runs `f` with a `Scope` for spawning tasks that borrow from the
+    /// caller, then waits for all of them. On a worker thread, the worker
+    /// keeps running tasks while it waits.
+    ///
+    /// If `f` or any spawned task panicked, the first panic is resumed once
+    /// every task has finished.
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        let scope = Scope {
+            handle: self.clone(),
+            data: Arc::new(ScopeData {
+                pending: AtomicUsize::new(0),
+                panic: Mutex::new(None),
+                waker: Mutex::new(None),
+                done: Condvar::new(),
+            }),
+            priority: WorkerThread::current().map_or(Priority::Normal, WorkerThread::priority),
+            _scope: PhantomData,
+            _env: PhantomData,
+        };
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.data.wait();
+
+        let result = result.unwrap_or_else(|payload| panic::resume_unwind(payload));
+        if let Some(payload) = scope.data.panic.lock().unwrap().take() {
+            panic::resume_unwind(payload);
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod scope_test {
+    use crate::work_stealing::scheduler::Scheduler;
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    #[test]
+    fn test_spawn_borrows_chunks() {
+        let scheduler = Scheduler::new(3);
+        let mut values: Vec<u32> = (0..1000).collect();
+
+        scheduler.scope(|s| {
+            for chunk in values.chunks_mut(100) {
+                s.spawn(move |_| {
+                    for value in chunk {
+                        *value *= 2;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(values, (0..1000).map(|i| i * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_nested_spawns_finish_before_return() {
+        let scheduler = Scheduler::new(2);
+        let count = AtomicUsize::new(0);
+
+        let result = scheduler.scope(|s| {
+            for _ in 0..10 {
+                s.spawn(|s| {
+                    for _ in 0..10 {
+                        s.spawn(|_| {
+                            count.fetch_add(1, Ordering::Relaxed);
+                        });
+                    }
+                });
+            }
+            "done"
+        });
+
+        assert_eq!(result, "done");
+        assert_eq!(count.load(Ordering::Relaxed), 100);
+    }
+
+    #[test]
+    fn test_scope_inside_pool() {
+        let scheduler = Scheduler::new(1);
+        let values = vec![1, 2, 3, 4];
+
+        let total = scheduler.install(|| {
+            let total = AtomicUsize::new(0);
+            scheduler.scope(|s| {
+                for value in &values {
+                    let total = &total;
+                    s.spawn(move |_| {
+                        total.fetch_add(*value, Ordering::Relaxed);
+                    });
+                }
+            });
+            total.into_inner()
+        });
+
+        assert_eq!(total, 10);
+    }
+
+    #[test]
+    fn test_panic_waits_for_other_tasks() {
+        let scheduler = Scheduler::new(2);
+        let count = AtomicUsize::new(0);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            scheduler.scope(|s| {
+                s.spawn(|_| panic!("task failed"));
+                for _ in 0..20 {
+                    s.spawn(|_| {
+                        count.fetch_add(1, Ordering::Relaxed);
+                    });
+                }
+            })
+        }));
+
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"task failed"));
+        assert_eq!(count.load(Ordering::Relaxed), 20);
+    }
+}
@@ -0,0 +1,303 @@
+//! Deterministic runs of a scheduler, for reproducing bugs that depend on
+//! how its workers interleave.
+//!
+//! `Builder::build_simulation` creates the workers without any threads of
+//! their own. Whoever drives the `Simulation` runs them one task at a time,
+//! and a PRNG seeded once decides which worker goes next and which peers
+//! it tries to rob. The same seed and the same calls replay the exact same
+//! schedule, so a failing test only needs its seed to fail again; tests
+//! take it from `seed_from_env` and a failing run prints it.
+//!
+//! Blocking calls made on the driving thread, such as `Handle::install`,
+//! `JoinHandle::join` or `Handle::block_on`, keep stepping the simulation
+//! until they can return, and so do workers waiting inside a task. Timers
+//! only stay deterministic with a `MockClock`.
+
+use std::{cell::RefCell, env, process, rc::Rc, thread};
+
+use super::{
+    rng::XorShift,
+    scheduler::{Handle, WorkerThread},
+};
+
+/// Environment variable `seed_from_env` reads the seed from.
+pub const SEED_VAR: &str = "MEMO_SIM_SEED";
+
+thread_local! {
+    /// Simulations alive on this thread, innermost last.
+    static ACTIVE: RefCell<Vec<Rc<State>>> = const { RefCell::new(Vec::new()) };
+}
+
+struct State {
+    seed: u64,
+    rng: RefCell<XorShift>,
+    handle: Handle,
+    workers: Vec<WorkerThread>,
+}
+
+impl State {
+    /// Steps randomly picked workers until `done` holds. After as many
+    /// idle picks in a row as there are workers, gives each worker one
+    /// more chance in turn, and returns `false` if none of them ran a
+    /// task either.
+    fn drive(&self, done: &dyn Fn() -> bool) -> bool {
+        let num_workers = self.workers.len();
+        let mut idle = 0;
+
+        while !done() {
+            let index = self.rng.borrow_mut().next_usize(num_workers);
+            if self.workers[index].step() {
+                idle = 0;
+                continue;
+            }
+
+            idle += 1;
+            if idle < num_workers {
+                continue;
+            }
+            if !self.workers.iter().any(WorkerThread::step) {
+                return false;
+            }
+            idle = 0;
+        }
+
+        true
+    }
+}
+
+/// A scheduler whose workers all run on the thread that drives it, in an
+/// order drawn from a seed. See the module docs.
+///
+/// The simulation is bound to the thread that built it. Dropping it runs
+/// whatever is still queued, unless the thread is panicking, in which case
+/// it prints the seed instead. Handles that outlive it run whatever they
+/// submit on the calling thread, like those of a dropped `Scheduler`.
+pub struct Simulation {
+    state: Rc<State>,
+}
+
+impl Simulation {
+    pub(crate) fn new(
+        seed: u64,
+        rng: XorShift,
+        handle: Handle,
+        workers: Vec<WorkerThread>,
+    ) -> Self {
+        let state = Rc::new(State {
+            seed,
+            rng: RefCell::new(rng),
+            handle,
+            workers,
+        });
+        ACTIVE.with(|active| active.borrow_mut().push(state.clone()));
+
+        Self { state }
+    }
+
+    /// Seed the schedule is drawn from.
+    pub fn seed(&self) -> u64 {
+        self.state.seed
+    }
+
+    /// Handle to submit tasks with. They only run while the simulation is
+    /// driven.
+    pub fn handle(&self) -> Handle {
+        self.state.handle.clone()
+    }
+
+    /// Runs tasks until none of the workers can find any.
+    pub fn run(&self) {
+        self.state.drive(&|| false);
+    }
+
+    /// Runs tasks until `done` holds.
+    ///
+    /// # Panics
+    ///
+    /// If the workers run out of tasks first. The message has the seed.
+    pub fn run_until<F>(&self, done: F)
+    where
+        F: Fn() -> bool,
+    {
+        if !self.state.drive(&done) {
+            panic!("simulation is stuck with seed {}", self.state.seed);
+        }
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        ACTIVE.with(|active| {
+            active
+                .borrow_mut()
+                .retain(|state| !Rc::ptr_eq(state, &self.state))
+        });
+
+        if thread::panicking() {
+            eprintln!(
+                "simulation failed with seed {0}, rerun with {1}={0}",
+                self.state.seed, SEED_VAR
+            );
+        } else {
+            self.run();
+            self.state.handle.close();
+        }
+    }
+}
+
+/// If a simulation is alive on this thread, steps it until `done` holds
+/// and returns `true`. Returns `false` right away otherwise.
+///
+/// Callers may have tasks borrowing their stack frame queued, so a
+/// simulation that gets stuck here aborts rather than unwinding past
+/// them.
+pub(crate) fn help_until(done: &dyn Fn() -> bool) -> bool {
+    let Some(state) = ACTIVE.with(|active| active.borrow().last().cloned()) else {
+        return false;
+    };
+
+    if !state.drive(done) {
+        eprintln!(
+            "simulation is stuck waiting inside a task with seed {0}, rerun with {1}={0}",
+            state.seed, SEED_VAR
+        );
+        process::abort();
+    }
+
+    true
+}
+
+/// Runs a single task of the simulation alive on this thread, if there is
+/// one. Returns whether a task ran.
+pub(crate) fn step() -> bool {
+    let Some(state) = ACTIVE.with(|active| active.borrow().last().cloned()) else {
+        return false;
+    };
+    let index = state.rng.borrow_mut().next_usize(state.workers.len());
+
+    state.workers[index].step()
+}
+
+/// Seed from the `MEMO_SIM_SEED` environment variable, to replay a failed
+/// run, or a random one.
+///
+/// # Panics
+///
+/// If the variable is set but not a `u64`.
+pub fn seed_from_env() -> u64 {
+    match env::var(SEED_VAR) {
+        Ok(seed) => seed
+            .trim()
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be a u64, got {:?}", SEED_VAR, seed)),
+        Err(_) => XorShift::from_entropy().next_u64(),
+    }
+}
+
+#[cfg(test)]
+mod simulation_test {
+    use super::*;
+    use crate::work_stealing::{join::join, schedule::Task, scheduler::Builder};
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::{Arc, Mutex},
+    };
+
+    type Trace = Arc<Mutex<Vec<(usize, usize)>>>;
+
+    /// Records which worker ran it, then submits two children, so the
+    /// workers have something to steal.
+    struct Node {
+        id: usize,
+        depth: usize,
+        trace: Trace,
+    }
+
+    impl Task for Node {
+        fn execute(self: Box<Self>) {
+            let worker = WorkerThread::current().unwrap().index();
+            self.trace.lock().unwrap().push((worker, self.id));
+
+            if self.depth > 0 {
+                let handle = Handle::current().unwrap();
+                for child in 1..=2 {
+                    handle.submit(Node {
+                        id: self.id * 2 + child,
+                        depth: self.depth - 1,
+                        trace: self.trace.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    fn trace(seed: u64) -> Vec<(usize, usize)> {
+        let simulation = Builder::new(4).build_simulation(seed);
+        let trace = Trace::default();
+
+        for id in 0..4 {
+            simulation.handle().submit(Node {
+                id: id * 100,
+                depth: 5,
+                trace: trace.clone(),
+            });
+        }
+        simulation.run();
+
+        let trace = trace.lock().unwrap().clone();
+        trace
+    }
+
+    #[test]
+    fn test_same_seed_same_schedule() {
+        let seed = seed_from_env();
+        let first = trace(seed);
+
+        assert_eq!(first.len(), 4 * 63);
+        assert_eq!(trace(seed), first, "seed {}", seed);
+    }
+
+    #[test]
+    fn test_seeds_change_schedule() {
+        let traces: Vec<_> = (1..=4).map(trace).collect();
+
+        assert!(traces.iter().any(|trace| *trace != traces[0]));
+    }
+
+    #[test]
+    fn test_blocking_calls_drive_simulation() {
+        let simulation = Builder::new(3).build_simulation(seed_from_env());
+        let handle = simulation.handle();
+
+        let on_test_thread = handle.install(|| {
+            let (a, b) = join(|| thread::current().id(), || thread::current().id());
+            assert_eq!(a, b);
+            a
+        });
+        assert_eq!(on_test_thread, thread::current().id());
+
+        let spawned = handle.spawn(|| join(|| 1, || 2));
+        assert_eq!(spawned.join().unwrap(), (1, 2));
+    }
+
+    #[test]
+    fn test_handle_outlives_simulation() {
+        let simulation = Builder::new(2).build_simulation(seed_from_env());
+        let handle = simulation.handle();
+        drop(simulation);
+
+        assert_eq!(handle.spawn(|| 7).join().unwrap(), 7);
+        assert_eq!(handle.install(|| join(|| 1, || 2)), (1, 2));
+    }
+
+    #[test]
+    fn test_stuck_simulation_reports_seed() {
+        let simulation = Builder::new(2).build_simulation(7);
+
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| simulation.run_until(|| false)))
+            .expect_err("an empty simulation cannot make progress");
+        let message = payload.downcast_ref::<String>().unwrap();
+
+        assert!(message.contains("seed 7"), "{}", message);
+    }
+}
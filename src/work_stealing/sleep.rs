@@ -0,0 +1,197 @@
+use std::{
+    hint,
+    sync::{
+        atomic::{fence, AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Rounds of exponentially longer spinning before an idle worker starts
+/// yielding.
+const SPIN_ROUNDS: u32 = 6;
+/// Rounds, counting the spinning ones, before an idle worker may park.
+const YIELD_ROUNDS: u32 = 16;
+
+/// Backs off an idle worker: spins first, then yields its time slice, and
+/// finally reports that it is time to park.
+pub(crate) struct Backoff {
+    rounds: u32,
+}
+
+impl Backoff {
+    pub(crate) fn new() -> Self {
+        Self { rounds: 0 }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.rounds = 0;
+    }
+
+    pub(crate) fn snooze(&mut self) {
+        if self.rounds < SPIN_ROUNDS {
+            for _ in 0..1 << self.rounds {
+                hint::spin_loop();
+            }
+        } else {
+            thread::yield_now();
+        }
+
+        self.rounds = (self.rounds + 1).min(YIELD_ROUNDS);
+    }
+
+    pub(crate) fn is_completed(&self) -> bool {
+        self.rounds >= YIELD_ROUNDS
+    }
+}
+
+/// How a call to `Sleep::park` went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Parked {
+    /// There was work after all, so the thread did not block.
+    Skipped,
+    /// Woken by a notification, or spuriously.
+    Woken,
+    TimedOut,
+}
+
+/// Where idle workers park until there is work again. Producers held back
+/// by a full queue wait for room the same way.
+///
+/// A worker about to park first counts itself in `sleepers`, then looks
+/// for work one last time; a thread that made work available first
+/// publishes it, then checks `sleepers`. Both sides put a `SeqCst` fence
+/// in between, so at least one of them sees the other, and since the final
+/// look and the wait happen under the lock that `notify_one` takes, a
+/// wakeup cannot slip in between them either.
+pub(crate) struct Sleep {
+    sleepers: AtomicUsize,
+    lock: Mutex<()>,
+    wakeup: Condvar,
+}
+
+impl Sleep {
+    pub(crate) fn new() -> Self {
+        Self {
+            sleepers: AtomicUsize::new(0),
+            lock: Mutex::new(()),
+            wakeup: Condvar::new(),
+        }
+    }
+
+    /// Wakes one parked worker, if there is any. Must be called after the
+    /// new work is visible to other threads.
+    pub(crate) fn notify_one(&self) {
+        fence(Ordering::SeqCst);
+        if self.sleepers.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        let _lock = self.lock.lock().unwrap();
+        self.wakeup.notify_one();
+    }
+
+    /// Wakes every parked worker, e.g. because one of them waits for
+    /// something other than new work. Same rules as `notify_one`.
+    pub(crate) fn notify_all(&self) {
+        fence(Ordering::SeqCst);
+        if self.sleepers.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        let _lock = self.lock.lock().unwrap();
+        self.wakeup.notify_all();
+    }
+
+    /// Blocks the current thread unless `has_work` says otherwise, until
+    /// it is notified or `timeout` returns a duration that has passed.
+    /// Spurious wakeups are possible, so callers look for work again.
+    pub(crate) fn park<W, T>(&self, has_work: W, timeout: T) -> Parked
+    where
+        W: Fn() -> bool,
+        T: Fn() -> Option<Duration>,
+    {
+        let lock = self.lock.lock().unwrap();
+        self.sleepers.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+
+        let parked = if has_work() {
+            Parked::Skipped
+        } else {
+            match timeout() {
+                Some(timeout) => match self.wakeup.wait_timeout(lock, timeout).unwrap() {
+                    (_, result) if result.timed_out() => Parked::TimedOut,
+                    _ => Parked::Woken,
+                },
+                None => {
+                    drop(self.wakeup.wait(lock).unwrap());
+                    Parked::Woken
+                }
+            }
+        };
+
+        self.sleepers.fetch_sub(1, Ordering::Relaxed);
+        parked
+    }
+}
+
+#[cfg(test)]
+mod sleep_test {
+    use super::*;
+    use std::sync::{atomic::AtomicBool, Arc};
+
+    #[test]
+    fn test_backoff_completes_after_yield_rounds() {
+        let mut backoff = Backoff::new();
+
+        for _ in 0..YIELD_ROUNDS {
+            assert!(!backoff.is_completed());
+            backoff.snooze();
+        }
+        assert!(backoff.is_completed());
+
+        backoff.reset();
+        assert!(!backoff.is_completed());
+    }
+
+    #[test]
+    fn test_park_returns_when_work_is_there() {
+        let sleep = Sleep::new();
+
+        assert_eq!(sleep.park(|| true, || None), Parked::Skipped);
+        assert_eq!(sleep.sleepers.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_park_times_out() {
+        let sleep = Sleep::new();
+
+        // Returning at all is the point; a spurious wakeup counts as woken.
+        let parked = sleep.park(|| false, || Some(Duration::from_millis(1)));
+        assert_ne!(parked, Parked::Skipped);
+    }
+
+    #[test]
+    fn test_notify_wakes_parked_thread() {
+        let sleep = Arc::new(Sleep::new());
+        let work = Arc::new(AtomicBool::new(false));
+
+        let sleeper = {
+            let sleep = sleep.clone();
+            let work = work.clone();
+            thread::spawn(move || {
+                while !work.load(Ordering::Relaxed) {
+                    sleep.park(|| work.load(Ordering::Relaxed), || None);
+                }
+            })
+        };
+
+        // Racing the sleeper on purpose: whether it already parked or not,
+        // it must not miss the work.
+        work.store(true, Ordering::Relaxed);
+        sleep.notify_one();
+
+        sleeper.join().unwrap();
+    }
+}
@@ -0,0 +1,16 @@
+//! Synchronization primitives the deques are built on. Under `--cfg loom`
+//! they come from loom instead of std, so the model-checking tests in
+//! `schedule` see every atomic access and lock and can explore all their
+//! interleavings.
+
+#[cfg(loom)]
+pub(crate) use loom::sync::{
+    atomic::{fence, AtomicIsize, AtomicPtr, AtomicUsize, Ordering},
+    Mutex, MutexGuard,
+};
+
+#[cfg(not(loom))]
+pub(crate) use std::sync::{
+    atomic::{fence, AtomicIsize, AtomicPtr, AtomicUsize, Ordering},
+    Mutex, MutexGuard,
+};
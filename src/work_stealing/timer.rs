@@ -0,0 +1,526 @@
+use std::{
+    array, mem,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use super::{
+    clock::Clock,
+    priority::Priority,
+    schedule::Task,
+    scheduler::{Handle, Job, WeakHandle},
+};
+
+/// Slots per level, and bits of the deadline each level looks at.
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const SLOT_MASK: u64 = SLOTS as u64 - 1;
+const LEVELS: usize = 6;
+/// Ticks covered by the whole wheel; deadlines further out are parked at
+/// the edge and placed again once it comes around.
+const MAX_SPAN: u64 = 1 << (SLOT_BITS * LEVELS as u32);
+
+struct Level<T> {
+    /// Bit `i` is set when `slots[i]` is non-empty.
+    occupied: u64,
+    slots: [Vec<(u64, T)>; SLOTS],
+}
+
+/// Hierarchical timer wheel counting in abstract ticks.
+///
+/// Level `n` has 64 slots of `64^n` ticks each. An entry sits on the lowest
+/// level whose current rotation still contains its deadline, and moves down
+/// a level each time its slot comes up, until it is due. Inserting and
+/// expiring are O(1) per level, however far apart the deadlines are.
+pub(crate) struct TimerWheel<T> {
+    elapsed: u64,
+    levels: [Level<T>; LEVELS],
+}
+
+impl<T> TimerWheel<T> {
+    pub(crate) fn new(start: u64) -> Self {
+        Self {
+            elapsed: start,
+            levels: array::from_fn(|_| Level {
+                occupied: 0,
+                slots: array::from_fn(|_| Vec::new()),
+            }),
+        }
+    }
+
+    /// Adds `item` to fire at tick `when`, or hands it back if that tick has
+    /// already passed.
+    pub(crate) fn insert(&mut self, when: u64, item: T) -> Result<(), T> {
+        if when <= self.elapsed {
+            return Err(item);
+        }
+
+        let target = when.min(self.elapsed + MAX_SPAN - 1);
+        let level = Self::level_for(self.elapsed, target);
+        let slot = ((target >> (SLOT_BITS * level as u32)) & SLOT_MASK) as usize;
+
+        let level = &mut self.levels[level];
+        level.slots[slot].push((when, item));
+        level.occupied |= 1 << slot;
+
+        Ok(())
+    }
+
+    /// Moves the wheel forward to tick `now` and returns everything that
+    /// came due, earliest first.
+    pub(crate) fn advance(&mut self, now: u64) -> Vec<T> {
+        let mut due = Vec::new();
+
+        while let Some((level, slot, deadline)) = self.next_expiration() {
+            if deadline > now {
+                break;
+            }
+
+            self.elapsed = deadline;
+            let entries = mem::take(&mut self.levels[level].slots[slot]);
+            self.levels[level].occupied &= !(1 << slot);
+
+            for (when, item) in entries {
+                if let Err(item) = self.insert(when, item) {
+                    due.push(item);
+                }
+            }
+        }
+
+        self.elapsed = self.elapsed.max(now);
+        due
+    }
+
+    /// Earliest tick at which `advance` has something to do. This can be
+    /// before the earliest deadline, when entries only need to move down a
+    /// level.
+    pub(crate) fn next_deadline(&self) -> Option<u64> {
+        self.next_expiration().map(|(_, _, deadline)| deadline)
+    }
+
+    /// Lowest level with an occupied slot, that slot and when it starts.
+    /// Entries on lower levels always expire before those on higher ones.
+    fn next_expiration(&self) -> Option<(usize, usize, u64)> {
+        self.levels
+            .iter()
+            .enumerate()
+            .find(|(_, level)| level.occupied != 0)
+            .map(|(index, level)| {
+                let slot_range = 1u64 << (SLOT_BITS * index as u32);
+                let level_range = slot_range << SLOT_BITS;
+                let current = ((self.elapsed / slot_range) & SLOT_MASK) as u32;
+
+                // The current slot itself can only hold entries for the next
+                // rotation, so it comes last.
+                let start = current + 1;
+                let distance = level.occupied.rotate_right(start).trailing_zeros();
+                let slot = ((start + distance) as u64 & SLOT_MASK) as usize;
+
+                let mut deadline = (self.elapsed & !(level_range - 1)) + slot as u64 * slot_range;
+                if deadline <= self.elapsed {
+                    // The slot belongs to the next rotation of this level.
+                    deadline += level_range;
+                }
+
+                (index, slot, deadline)
+            })
+    }
+
+    fn level_for(elapsed: u64, when: u64) -> usize {
+        let masked = (elapsed ^ when) | SLOT_MASK;
+        let significant = (u64::BITS - 1 - masked.leading_zeros()) / SLOT_BITS;
+
+        (significant as usize).min(LEVELS - 1)
+    }
+}
+
+/// Shared between a timer's handle and its pending task.
+struct TimerState {
+    cancelled: AtomicBool,
+}
+
+/// Handle to a task scheduled with `spawn_after` or `spawn_every`.
+///
+/// Dropping the handle leaves the timer running.
+#[derive(Clone)]
+pub struct TimerHandle {
+    state: Arc<TimerState>,
+}
+
+impl TimerHandle {
+    /// Stops the timer. A task that has not been queued yet never runs,
+    /// and a periodic task is not run again. A run that is already queued
+    /// or in progress is not interrupted.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Acquire)
+    }
+}
+
+struct Timer {
+    state: Arc<TimerState>,
+    job: Box<Job>,
+}
+
+/// Timer wheel of a scheduler along with the clock it runs on. Workers
+/// call `poll` while looking for work and queue whatever came due on their
+/// own deque.
+pub(crate) struct Timers {
+    clock: Arc<dyn Clock>,
+    wheel: Mutex<TimerWheel<Timer>>,
+    /// Copy of the wheel's next deadline, so `poll` can skip the lock.
+    next_deadline: AtomicU64,
+}
+
+impl Timers {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Self {
+        let start = to_ticks(clock.now());
+
+        Self {
+            clock,
+            wheel: Mutex::new(TimerWheel::new(start)),
+            next_deadline: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    /// First tick at or after `delay` from now. Ticks are milliseconds.
+    fn deadline_after(&self, delay: Duration) -> u64 {
+        let deadline = self.clock.now().saturating_add(delay);
+
+        deadline
+            .as_nanos()
+            .div_ceil(1_000_000)
+            .min(u64::MAX as u128) as u64
+    }
+
+    /// Hands every timer that is due and not cancelled to `queue`. Does
+    /// nothing if another thread is already at it.
+    pub(crate) fn poll<F>(&self, mut queue: F)
+    where
+        F: FnMut(Box<Job>),
+    {
+        let now = to_ticks(self.clock.now());
+        if now < self.next_deadline.load(Ordering::Acquire) {
+            return;
+        }
+
+        let Ok(mut wheel) = self.wheel.try_lock() else {
+            return;
+        };
+        let due = wheel.advance(now);
+        self.update_next_deadline(&wheel);
+        drop(wheel);
+
+        for timer in due {
+            if !timer.state.cancelled.load(Ordering::Acquire) {
+                queue(timer.job);
+            }
+        }
+    }
+
+    /// Adds a timer, or returns its job if it is already due.
+    fn insert(&self, when: u64, timer: Timer) -> Option<Box<Job>> {
+        let mut wheel = self.wheel.lock().unwrap();
+        let result = wheel.insert(when, timer);
+        self.update_next_deadline(&wheel);
+
+        result.err().map(|timer| timer.job)
+    }
+
+    /// How long an idle worker may park before it has to `poll` again, or
+    /// `None` if there are no timers. Clocks that jump ahead wake the
+    /// workers themselves, see `Clock::subscribe`.
+    pub(crate) fn park_timeout(&self) -> Option<Duration> {
+        let next = self.next_deadline.load(Ordering::Acquire);
+        if next == u64::MAX {
+            return None;
+        }
+
+        let now = to_ticks(self.clock.now());
+        Some(Duration::from_millis(next.saturating_sub(now)))
+    }
+
+    fn update_next_deadline(&self, wheel: &TimerWheel<Timer>) {
+        let next = wheel.next_deadline().unwrap_or(u64::MAX);
+        self.next_deadline.store(next, Ordering::Release);
+    }
+}
+
+fn to_ticks(time: Duration) -> u64 {
+    time.as_millis().min(u64::MAX as u128) as u64
+}
+
+/// Task behind `spawn_every`, which puts itself back on the wheel after
+/// each run.
+struct Periodic<F> {
+    f: F,
+    interval: u64,
+    deadline: u64,
+    state: Arc<TimerState>,
+    scheduler: WeakHandle,
+}
+
+impl<F> Task for Periodic<F>
+where
+    F: FnMut() + Send + 'static,
+{
+    fn execute(mut self: Box<Self>) {
+        if self.state.cancelled.load(Ordering::Acquire) {
+            return;
+        }
+
+        (self.f)();
+
+        if let Some(handle) = self.scheduler.upgrade() {
+            // Runs that fell behind are skipped rather than run back to
+            // back, and since this only happens after `f` returned, runs
+            // never overlap.
+            let now = to_ticks(handle.timers().clock.now());
+            self.deadline = (self.deadline + self.interval).max(now);
+
+            let state = self.state.clone();
+            handle.schedule_timer(self.deadline, state, self);
+        }
+    }
+}
+
+impl Handle {
+    /// Runs `task` once `delay` has passed on the scheduler's clock.
+    ///
+    /// Timers have millisecond resolution and fire the next time a worker
+    /// looks for work, which queues the task on its own deque.
+    pub fn spawn_after<T>(&self, delay: Duration, task: T) -> TimerHandle
+    where
+        T: Task + Send + 'static,
+    {
+        let state = Arc::new(TimerState {
+            cancelled: AtomicBool::new(false),
+        });
+
+        let when = self.timers().deadline_after(delay);
+        self.schedule_timer(when, state.clone(), Box::new(task));
+
+        TimerHandle { state }
+    }
+
+    /// Runs `f` every `interval`, starting one `interval` from now, until
+    /// the returned handle is cancelled. A run that takes longer than
+    /// `interval` delays the next one instead of overlapping it. If `f`
+    /// panics, it is not run again.
+    pub fn spawn_every<F>(&self, interval: Duration, f: F) -> TimerHandle
+    where
+        F: FnMut() + Send + 'static,
+    {
+        assert!(!interval.is_zero(), "interval must be non-zero");
+
+        let state = Arc::new(TimerState {
+            cancelled: AtomicBool::new(false),
+        });
+        let when = self.timers().deadline_after(interval);
+        let task = Periodic {
+            f,
+            interval: interval.as_nanos().div_ceil(1_000_000) as u64,
+            deadline: when,
+            state: state.clone(),
+            scheduler: self.downgrade(),
+        };
+
+        self.schedule_timer(when, state.clone(), Box::new(task));
+
+        TimerHandle { state }
+    }
+
+    fn schedule_timer(&self, when: u64, state: Arc<TimerState>, job: Box<Job>) {
+        match self.timers().insert(when, Timer { state, job }) {
+            Some(job) => self.push_job(job, Priority::Normal),
+            // A parked worker may have to wake up earlier now.
+            None => self.notify_worker(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod timer_test {
+    use super::*;
+    use crate::work_stealing::{
+        clock::MockClock,
+        rng::XorShift,
+        scheduler::{Builder, Scheduler},
+    };
+    use std::sync::{atomic::AtomicUsize, mpsc};
+
+    #[test]
+    fn test_wheel_fires_in_deadline_order() {
+        let mut wheel = TimerWheel::new(0);
+
+        for when in [70, 5, 4_100, 64, 300_000, 6] {
+            wheel.insert(when, when).unwrap();
+        }
+
+        assert_eq!(wheel.advance(4), Vec::<u64>::new());
+        assert_eq!(wheel.advance(64), vec![5, 6, 64]);
+        assert_eq!(wheel.advance(100_000), vec![70, 4_100]);
+        assert_eq!(wheel.advance(299_999), Vec::<u64>::new());
+        assert_eq!(wheel.advance(300_000), vec![300_000]);
+        assert_eq!(wheel.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_wheel_rejects_past_deadlines() {
+        let mut wheel = TimerWheel::new(10);
+
+        assert_eq!(wheel.insert(10, "now"), Err("now"));
+        assert_eq!(wheel.insert(11, "soon"), Ok(()));
+        assert_eq!(wheel.next_deadline(), Some(11));
+    }
+
+    #[test]
+    fn test_wheel_small_steps_across_levels() {
+        let mut wheel = TimerWheel::new(0);
+        let deadlines = [1, 63, 64, 65, 4_095, 4_096, 4_097, 262_143, 262_144];
+
+        for when in deadlines {
+            wheel.insert(when, when).unwrap();
+        }
+
+        let mut fired = Vec::new();
+        for now in 1..=262_144 {
+            for when in wheel.advance(now) {
+                assert_eq!(when, now);
+                fired.push(when);
+            }
+        }
+
+        assert_eq!(fired, deadlines);
+    }
+
+    #[test]
+    fn test_wheel_beyond_span() {
+        let mut wheel = TimerWheel::new(MAX_SPAN - 1);
+        let far = 3 * MAX_SPAN + 17;
+
+        wheel.insert(MAX_SPAN, "edge").unwrap();
+        wheel.insert(far, "far").unwrap();
+
+        assert_eq!(wheel.advance(MAX_SPAN), vec!["edge"]);
+        assert_eq!(wheel.advance(far - 1), Vec::<&str>::new());
+        assert_eq!(wheel.advance(far), vec!["far"]);
+    }
+
+    #[test]
+    fn test_wheel_random_deadlines() {
+        let mut rng = XorShift::new(7);
+        let mut wheel = TimerWheel::new(0);
+        let mut pending = 0;
+        let mut now = 0;
+
+        while now < 1 << 22 {
+            for _ in 0..rng.next_usize(4) {
+                let when = now + 1 + rng.next_u64() % (1 << 20);
+                wheel.insert(when, when).unwrap();
+                pending += 1;
+            }
+
+            let before = now;
+            now += 1 + rng.next_u64() % 5_000;
+
+            let fired = wheel.advance(now);
+            assert!(fired.windows(2).all(|pair| pair[0] <= pair[1]));
+            assert!(fired.iter().all(|&when| before < when && when <= now));
+            pending -= fired.len();
+        }
+
+        assert_eq!(wheel.advance(u64::MAX).len(), pending);
+    }
+
+    fn scheduler_with_mock_clock() -> (Scheduler, Arc<MockClock>) {
+        let clock = Arc::new(MockClock::new());
+        let scheduler = Builder::new(1).clock(clock.clone()).build();
+
+        (scheduler, clock)
+    }
+
+    #[test]
+    fn test_spawn_after_waits_for_clock() {
+        let (scheduler, clock) = scheduler_with_mock_clock();
+        let fired = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+
+        {
+            let fired = fired.clone();
+            scheduler
+                .handle()
+                .spawn_after(Duration::from_secs(10), move || {
+                    fired.store(true, Ordering::Relaxed);
+                    sender.send(()).unwrap();
+                });
+        }
+
+        clock.advance(Duration::from_secs(5));
+        // Workers check timers before picking up a task, so this runs after
+        // the timer had its chance to fire early.
+        let early = fired.clone();
+        assert!(!scheduler
+            .spawn(move || early.load(Ordering::Relaxed))
+            .join()
+            .unwrap());
+
+        clock.advance(Duration::from_secs(5));
+        receiver.recv().unwrap();
+        assert!(fired.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_cancel_before_due() {
+        let (scheduler, clock) = scheduler_with_mock_clock();
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let timer = {
+            let count = count.clone();
+            scheduler
+                .handle()
+                .spawn_after(Duration::from_millis(20), move || {
+                    count.fetch_add(1, Ordering::Relaxed);
+                })
+        };
+        timer.cancel();
+        clock.advance(Duration::from_millis(20));
+        scheduler.shutdown();
+
+        assert!(timer.is_cancelled());
+        assert_eq!(count.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_spawn_every_until_cancelled() {
+        let (scheduler, clock) = scheduler_with_mock_clock();
+        let count = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = mpsc::channel();
+
+        let timer = {
+            let count = count.clone();
+            scheduler
+                .handle()
+                .spawn_every(Duration::from_millis(100), move || {
+                    count.fetch_add(1, Ordering::Relaxed);
+                    sender.send(()).unwrap();
+                })
+        };
+
+        for _ in 0..3 {
+            clock.advance(Duration::from_millis(100));
+            receiver.recv().unwrap();
+        }
+        timer.cancel();
+        clock.advance(Duration::from_millis(100));
+        scheduler.shutdown();
+
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+    }
+}
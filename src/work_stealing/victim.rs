@@ -0,0 +1,589 @@
+use std::{
+    fs, io,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use super::rng::XorShift;
+
+/// Decides which peers a worker tries to steal from, and in what order.
+///
+/// Every worker gets its own selector, cloned from the one given to
+/// `Builder::victim_selector`, so selectors can keep per-thief state
+/// without any synchronization.
+pub trait VictimSelector: Send {
+    /// Short name that tells strategies apart in `StealStats`.
+    fn name(&self) -> &'static str;
+
+    /// Called once for every scheduler, on the selector that all of its
+    /// workers' selectors are then cloned from. Selectors that share state
+    /// between the workers of one scheduler set it up here.
+    fn bind(&mut self, num_workers: usize) {
+        let _ = num_workers;
+    }
+
+    /// Called on the worker thread before it first looks for work.
+    fn start(&mut self, worker: usize, num_workers: usize);
+
+    /// Pushes the workers to try onto the empty `victims`, most promising
+    /// first. The thief itself and out of range indices are skipped.
+    fn select(&mut self, victims: &mut Vec<usize>);
+
+    /// Reports whether stealing from `victim` turned up a task.
+    fn record(&mut self, victim: usize, success: bool) {
+        let _ = (victim, success);
+    }
+}
+
+/// Pushes every worker but `worker`, starting at `start` and wrapping
+/// around.
+fn push_rotated(victims: &mut Vec<usize>, start: usize, worker: usize, num_workers: usize) {
+    victims.extend(
+        (0..num_workers)
+            .map(|offset| (start + offset) % num_workers)
+            .filter(|&victim| victim != worker),
+    );
+}
+
+/// Tries every peer in order, starting from a random one.
+#[derive(Debug, Clone)]
+pub struct RandomSelector {
+    worker: usize,
+    num_workers: usize,
+    seed: Option<u64>,
+    rng: XorShift,
+}
+
+impl RandomSelector {
+    pub fn new() -> Self {
+        Self {
+            worker: 0,
+            num_workers: 1,
+            seed: None,
+            rng: XorShift::new(0),
+        }
+    }
+
+    /// Picks victims in an order that only depends on `seed`, rather than
+    /// seeding every worker from entropy.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            seed: Some(seed),
+            ..Self::new()
+        }
+    }
+}
+
+impl Default for RandomSelector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VictimSelector for RandomSelector {
+    fn name(&self) -> &'static str {
+        "random"
+    }
+
+    fn start(&mut self, worker: usize, num_workers: usize) {
+        self.worker = worker;
+        self.num_workers = num_workers;
+        self.rng = self.seed.map_or_else(XorShift::from_entropy, XorShift::new);
+    }
+
+    fn select(&mut self, victims: &mut Vec<usize>) {
+        let start = self.rng.next_usize(self.num_workers);
+        push_rotated(victims, start, self.worker, self.num_workers);
+    }
+}
+
+/// Tries every peer in order, starting one further each time.
+#[derive(Debug, Clone, Default)]
+pub struct RoundRobinSelector {
+    worker: usize,
+    num_workers: usize,
+    next: usize,
+}
+
+impl RoundRobinSelector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl VictimSelector for RoundRobinSelector {
+    fn name(&self) -> &'static str {
+        "round-robin"
+    }
+
+    fn start(&mut self, worker: usize, num_workers: usize) {
+        self.worker = worker;
+        self.num_workers = num_workers;
+        self.next = (worker + 1) % num_workers;
+    }
+
+    fn select(&mut self, victims: &mut Vec<usize>) {
+        push_rotated(victims, self.next, self.worker, self.num_workers);
+        self.next = (self.next + 1) % self.num_workers;
+    }
+}
+
+/// Goes back to the last peer it stole from, as long as that keeps
+/// working, and otherwise tries peers in random order. Works well when a
+/// few workers produce most of the tasks.
+#[derive(Debug, Clone)]
+pub struct LastSuccessfulSelector {
+    random: RandomSelector,
+    last: Option<usize>,
+}
+
+impl LastSuccessfulSelector {
+    pub fn new() -> Self {
+        Self {
+            random: RandomSelector::new(),
+            last: None,
+        }
+    }
+}
+
+impl Default for LastSuccessfulSelector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VictimSelector for LastSuccessfulSelector {
+    fn name(&self) -> &'static str {
+        "last-successful"
+    }
+
+    fn start(&mut self, worker: usize, num_workers: usize) {
+        self.random.start(worker, num_workers);
+    }
+
+    fn select(&mut self, victims: &mut Vec<usize>) {
+        let Some(last) = self.last else {
+            return self.random.select(victims);
+        };
+
+        victims.push(last);
+        self.random.select(victims);
+        if let Some(position) = victims[1..].iter().position(|&victim| victim == last) {
+            victims.remove(position + 1);
+        }
+    }
+
+    fn record(&mut self, victim: usize, success: bool) {
+        if success {
+            self.last = Some(victim);
+        } else if self.last == Some(victim) {
+            self.last = None;
+        }
+    }
+}
+
+/// Where a CPU sits in the machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CpuInfo {
+    /// Lowest-numbered CPU sharing the physical core.
+    core: usize,
+    node: usize,
+}
+
+/// CPU layout of the machine, as far as stealing cares about it.
+#[derive(Debug, Clone, Default)]
+pub struct Topology {
+    cpus: Vec<Option<CpuInfo>>,
+}
+
+impl Topology {
+    /// Reads the layout from `/sys` on Linux. Anywhere else, or if that
+    /// fails, every CPU counts as equally far from every other.
+    pub fn detect() -> Self {
+        if cfg!(target_os = "linux") {
+            Self::from_sys(Path::new("/sys/devices/system")).unwrap_or_default()
+        } else {
+            Self::default()
+        }
+    }
+
+    /// Reads `cpu/cpuN/topology` for cores and packages and `node/nodeN`
+    /// for NUMA nodes under `root`. Without NUMA information, each package
+    /// counts as a node.
+    fn from_sys(root: &Path) -> io::Result<Self> {
+        let mut cpus = Vec::new();
+
+        for (cpu, path) in numbered_entries(&root.join("cpu"), "cpu")? {
+            let topology = path.join("topology");
+            let core = read_cpu_list(&topology.join("core_cpus_list"))
+                .or_else(|_| read_cpu_list(&topology.join("thread_siblings_list")))
+                .ok()
+                .and_then(|siblings| siblings.into_iter().min())
+                .unwrap_or(cpu);
+            let package = fs::read_to_string(topology.join("physical_package_id"))
+                .ok()
+                .and_then(|id| id.trim().parse().ok())
+                .unwrap_or(0);
+
+            if cpus.len() <= cpu {
+                cpus.resize(cpu + 1, None);
+            }
+            cpus[cpu] = Some(CpuInfo {
+                core,
+                node: package,
+            });
+        }
+
+        if let Ok(nodes) = numbered_entries(&root.join("node"), "node") {
+            for (node, path) in nodes {
+                for cpu in read_cpu_list(&path.join("cpulist"))? {
+                    if let Some(Some(info)) = cpus.get_mut(cpu) {
+                        info.node = node;
+                    }
+                }
+            }
+        }
+
+        Ok(Self { cpus })
+    }
+
+    /// 0 for the same physical core, 1 for the same node and 2 for
+    /// anything further or unknown.
+    pub fn distance(&self, a: usize, b: usize) -> usize {
+        let info = |cpu: usize| self.cpus.get(cpu).copied().flatten();
+
+        match (info(a), info(b)) {
+            (Some(a), Some(b)) if a.core == b.core => 0,
+            (Some(a), Some(b)) if a.node == b.node => 1,
+            _ => 2,
+        }
+    }
+}
+
+/// Entries of `dir` named `prefix` followed by a number.
+fn numbered_entries(dir: &Path, prefix: &str) -> io::Result<Vec<(usize, std::path::PathBuf)>> {
+    let mut entries = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let number = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(prefix))
+            .and_then(|number| number.parse().ok());
+
+        if let Some(number) = number {
+            entries.push((number, entry.path()));
+        }
+    }
+
+    Ok(entries)
+}
+
+fn read_cpu_list(path: &Path) -> io::Result<Vec<usize>> {
+    parse_cpu_list(&fs::read_to_string(path)?)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed CPU list"))
+}
+
+/// Parses the kernel's CPU list format, such as `0-3,8,10-11`.
+fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => cpus.extend(first.parse::<usize>().ok()?..=last.parse().ok()?),
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+
+    Some(cpus)
+}
+
+/// CPU the current thread last ran on.
+fn current_cpu() -> Option<usize> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+
+    let stat = fs::read_to_string("/proc/thread-self/stat").ok()?;
+    // Field 39 of the line; counting starts after the parenthesized name,
+    // which may contain spaces, at field 3.
+    stat[stat.rfind(')')? + 2..]
+        .split(' ')
+        .nth(36)?
+        .parse()
+        .ok()
+}
+
+/// How many selections pass before a worker checks which CPU it is on.
+const CPU_REFRESH_INTERVAL: usize = 64;
+
+/// Tries peers running close by first: on the same physical core, then on
+/// the same NUMA node, then everyone else. Ties are broken randomly.
+///
+/// Worker threads are not pinned, so each worker looks up the CPU it runs
+/// on every so often and shares it with the other workers of its
+/// scheduler, through a table that `bind` sets up.
+#[derive(Debug, Clone)]
+pub struct LocalitySelector {
+    topology: Arc<Topology>,
+    /// Last known CPU of every worker, by index.
+    cpus: Arc<[AtomicUsize]>,
+    random: RandomSelector,
+    worker: usize,
+    selections: usize,
+}
+
+impl LocalitySelector {
+    pub fn new() -> Self {
+        Self::with_topology(Topology::detect())
+    }
+
+    pub fn with_topology(topology: Topology) -> Self {
+        Self {
+            topology: Arc::new(topology),
+            cpus: Arc::new([]),
+            random: RandomSelector::new(),
+            worker: 0,
+            selections: 0,
+        }
+    }
+
+    fn cpu_of(&self, worker: usize) -> usize {
+        self.cpus
+            .get(worker)
+            .map_or(usize::MAX, |cpu| cpu.load(Ordering::Relaxed))
+    }
+}
+
+impl Default for LocalitySelector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VictimSelector for LocalitySelector {
+    fn name(&self) -> &'static str {
+        "locality"
+    }
+
+    fn bind(&mut self, num_workers: usize) {
+        self.cpus = (0..num_workers)
+            .map(|_| AtomicUsize::new(usize::MAX))
+            .collect();
+    }
+
+    fn start(&mut self, worker: usize, num_workers: usize) {
+        self.worker = worker;
+        self.random.start(worker, num_workers);
+
+        // Used without `bind`, the selector only knows its own CPU.
+        if self.cpus.len() != num_workers {
+            self.bind(num_workers);
+        }
+    }
+
+    fn select(&mut self, victims: &mut Vec<usize>) {
+        if self.selections.is_multiple_of(CPU_REFRESH_INTERVAL) {
+            if let (Some(cpu), Some(own)) = (current_cpu(), self.cpus.get(self.worker)) {
+                own.store(cpu, Ordering::Relaxed);
+            }
+        }
+        self.selections += 1;
+
+        self.random.select(victims);
+        let own = self.cpu_of(self.worker);
+        victims.sort_by_key(|&victim| self.topology.distance(own, self.cpu_of(victim)));
+    }
+}
+
+/// How well stealing went so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StealStats {
+    /// `VictimSelector::name` of the strategy in use.
+    pub strategy: &'static str,
+    /// Steal attempts on a peer's deque.
+    pub attempts: u64,
+    /// Attempts that came back with at least one task.
+    pub successes: u64,
+}
+
+impl StealStats {
+    /// Share of attempts that found something, or 0 before the first one.
+    pub fn success_rate(&self) -> f64 {
+        if self.attempts == 0 {
+            return 0.0;
+        }
+
+        self.successes as f64 / self.attempts as f64
+    }
+}
+
+#[cfg(test)]
+mod victim_test {
+    use super::*;
+
+    fn select<S: VictimSelector>(selector: &mut S) -> Vec<usize> {
+        let mut victims = Vec::new();
+        selector.select(&mut victims);
+        victims
+    }
+
+    fn sorted(mut victims: Vec<usize>) -> Vec<usize> {
+        victims.sort();
+        victims
+    }
+
+    #[test]
+    fn test_selectors_try_every_peer_once() {
+        let mut selectors: Vec<Box<dyn VictimSelector>> = vec![
+            Box::new(RandomSelector::new()),
+            Box::new(RoundRobinSelector::new()),
+            Box::new(LastSuccessfulSelector::new()),
+            Box::new(LocalitySelector::with_topology(Topology::default())),
+        ];
+
+        for selector in &mut selectors {
+            selector.start(2, 5);
+
+            for round in 0..10 {
+                let mut victims = Vec::new();
+                selector.select(&mut victims);
+                selector.record(victims[0], round % 3 == 0);
+
+                assert_eq!(sorted(victims), vec![0, 1, 3, 4], "{}", selector.name());
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_robin_rotates() {
+        let mut selector = RoundRobinSelector::new();
+        selector.start(0, 4);
+
+        assert_eq!(select(&mut selector), vec![1, 2, 3]);
+        assert_eq!(select(&mut selector), vec![2, 3, 1]);
+        assert_eq!(select(&mut selector), vec![3, 1, 2]);
+        assert_eq!(select(&mut selector), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_last_successful_goes_first() {
+        let mut selector = LastSuccessfulSelector::new();
+        selector.start(0, 8);
+
+        selector.record(5, true);
+        assert_eq!(select(&mut selector)[0], 5);
+        assert_eq!(select(&mut selector).len(), 7);
+
+        selector.record(5, false);
+        selector.record(3, false);
+        assert!(selector.last.is_none());
+    }
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(
+            parse_cpu_list("0-3,8,10-11\n"),
+            Some(vec![0, 1, 2, 3, 8, 10, 11])
+        );
+        assert_eq!(parse_cpu_list("5"), Some(vec![5]));
+        assert_eq!(parse_cpu_list(""), Some(vec![]));
+        assert_eq!(parse_cpu_list("1-x"), None);
+    }
+
+    /// Two nodes with two cores of two hardware threads each, in a
+    /// directory of its own per `test`, since tests run in parallel.
+    fn fake_sys(test: &str) -> std::path::PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("memo-topology-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&root);
+
+        for cpu in 0..8 {
+            let topology = root.join(format!("cpu/cpu{}/topology", cpu));
+            fs::create_dir_all(&topology).unwrap();
+
+            let core = cpu / 2 * 2;
+            fs::write(
+                topology.join("core_cpus_list"),
+                format!("{}-{}\n", core, core + 1),
+            )
+            .unwrap();
+            fs::write(topology.join("physical_package_id"), "0\n").unwrap();
+        }
+        for (node, cpus) in [(0, "0-3"), (1, "4-7")] {
+            let node = root.join(format!("node/node{}", node));
+            fs::create_dir_all(&node).unwrap();
+            fs::write(node.join("cpulist"), cpus).unwrap();
+        }
+        fs::create_dir_all(root.join("cpu/cpufreq")).unwrap();
+
+        root
+    }
+
+    #[test]
+    fn test_topology_from_sys() {
+        let root = fake_sys("from-sys");
+        let topology = Topology::from_sys(&root).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(topology.distance(0, 1), 0);
+        assert_eq!(topology.distance(0, 2), 1);
+        assert_eq!(topology.distance(3, 4), 2);
+        assert_eq!(topology.distance(6, 7), 0);
+        assert_eq!(topology.distance(0, 99), 2);
+    }
+
+    #[test]
+    fn test_locality_prefers_close_peers() {
+        let root = fake_sys("close-peers");
+        let topology = Topology::from_sys(&root).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        let mut selector = LocalitySelector::with_topology(topology);
+        selector.start(0, 4);
+        let cpus = selector.cpus.clone();
+        for (worker, cpu) in [(1, 5), (2, 1), (3, 3)] {
+            cpus[worker].store(cpu, Ordering::Relaxed);
+        }
+
+        // Skips the first look at the real CPU, so worker 0 stays on CPU 0.
+        selector.selections = 1;
+        cpus[0].store(0, Ordering::Relaxed);
+
+        assert_eq!(select(&mut selector), vec![2, 3, 1]);
+    }
+
+    #[test]
+    fn test_locality_table_per_scheduler() {
+        let original = LocalitySelector::with_topology(Topology::default());
+
+        // As `Builder::victim_selector` does for a 1 and then a 4 worker
+        // scheduler.
+        let mut small = original.clone();
+        small.bind(1);
+        let mut large = original.clone();
+        large.bind(4);
+
+        let mut worker = large.clone();
+        worker.start(3, 4);
+        assert_eq!(sorted(select(&mut worker)), vec![0, 1, 2]);
+        assert_eq!(small.cpus.len(), 1);
+        assert!(Arc::ptr_eq(&worker.cpus, &large.cpus));
+    }
+
+    #[test]
+    fn test_success_rate() {
+        let stats = |attempts, successes| StealStats {
+            strategy: "random",
+            attempts,
+            successes,
+        };
+
+        assert_eq!(stats(0, 0).success_rate(), 0.0);
+        assert_eq!(stats(4, 1).success_rate(), 0.25);
+    }
+}